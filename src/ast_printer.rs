@@ -6,10 +6,10 @@ use crate::{
 
 pub fn example() {
     let u = Expr::Unary(
-        Token::new(TokenType::Minus, "-".to_owned(), None, 1),
+        Token::new(TokenType::Minus, "-".to_owned(), None, 1, 1),
         Box::new(Expr::Literal(Literal::Double(123.0))),
     );
-    let s = Token::new(TokenType::Star, "*".to_owned(), None, 1);
+    let s = Token::new(TokenType::Star, "*".to_owned(), None, 1, 1);
     let g = Expr::Grouping(Box::new(Expr::Literal(Literal::Double(45.67))));
     let e = Expr::Binary(Box::new(u), s, Box::new(g));
     println!("{}", print(&e));
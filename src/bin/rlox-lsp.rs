@@ -0,0 +1,13 @@
+use std::io::{stdin, stdout};
+
+use rlox::lsp::Server;
+
+fn main() {
+    match Server::new().run(stdin().lock(), stdout().lock()) {
+        Ok(clean) => std::process::exit(if clean { 0 } else { 1 }),
+        Err(error) => {
+            eprintln!("rlox-lsp: {}", error);
+            std::process::exit(1)
+        }
+    }
+}
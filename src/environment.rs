@@ -3,10 +3,17 @@ use std::collections::HashMap;
 
 #[derive(Debug, Clone)]
 pub struct Environment {
-    values: HashMap<String, Literal>,
+    /* A variable declared without an initializer has no value until it is assigned. */
+    values: HashMap<String, Option<Literal>>,
     enclosing: Option<Box<Environment>>,
 }
 
+impl Default for Environment {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Environment {
     pub fn new() -> Environment {
         Environment {
@@ -22,16 +29,18 @@ impl Environment {
         }
     }
 
-    pub fn define(&mut self, name: String, value: Literal) {
+    pub fn define(&mut self, name: String, value: Option<Literal>) {
         self.values.insert(name, value);
     }
 
-    pub fn get(&self, name: &Token) -> Option<&Literal> {
+    /* Returns None if the variable is undefined and Some(None) if it was
+     * declared but never assigned. */
+    pub fn get(&self, name: &Token) -> Option<&Option<Literal>> {
         let r1 = self.values.get(&name.lexeme);
         if r1.is_some() {
             r1
         } else if let Some(ref enclosing) = self.enclosing {
-            enclosing.get(&name)
+            enclosing.get(name)
         } else {
             None
         }
@@ -39,15 +48,24 @@ impl Environment {
 
     pub fn assign(&mut self, name: &Token, value: &Literal) -> bool {
         if self.values.contains_key(&name.lexeme) {
-            self.values.insert(name.lexeme.clone(), value.clone());
+            self.values.insert(name.lexeme.clone(), Some(value.clone()));
             true
         } else if let Some(ref mut enclosing) = self.enclosing {
-            enclosing.assign(&name, value)
+            enclosing.assign(name, value)
         } else {
             false
         }
     }
 
+    /* Names of every variable visible from this environment. */
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.values.keys().map(String::as_str).collect();
+        if let Some(ref enclosing) = self.enclosing {
+            names.extend(enclosing.names());
+        }
+        names
+    }
+
     pub fn enclosing(&self) -> Option<Box<Environment>> {
         self.enclosing.clone()
     }
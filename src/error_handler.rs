@@ -1,8 +1,33 @@
 use crate::{interpreter::InterpErr, parser::ParseErr, token::Token, token_type::TokenType};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/* A reported problem together with where in the source it was found. */
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    /* One-based byte column of the offending token, if one is known. */
+    pub column: Option<usize>,
+    pub length: usize,
+    pub message: String,
+}
+
 pub struct ErrorHandler {
     had_error: bool,
     had_runtime_error: bool,
+    diagnostics: Vec<Diagnostic>,
+    quiet: bool,
+}
+
+impl Default for ErrorHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ErrorHandler {
@@ -10,6 +35,16 @@ impl ErrorHandler {
         ErrorHandler {
             had_error: false,
             had_runtime_error: false,
+            diagnostics: Vec::new(),
+            quiet: false,
+        }
+    }
+
+    /* An error handler that only collects diagnostics instead of printing them. */
+    pub fn quiet() -> ErrorHandler {
+        ErrorHandler {
+            quiet: true,
+            ..ErrorHandler::new()
         }
     }
 
@@ -18,7 +53,10 @@ impl ErrorHandler {
     }
 
     pub fn report(&mut self, line: usize, location: &str, message: &str) {
-        println!("[line {}] Error{}: {}", line, location, message);
+        self.record(Severity::Error, line, None, 0, message);
+        if !self.quiet {
+            println!("[line {}] Error{}: {}", line, location, message);
+        }
         self.had_error = true;
     }
 
@@ -38,20 +76,69 @@ impl ErrorHandler {
         self.had_runtime_error = false;
     }
 
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
     pub fn parse_error(&mut self, token: &Token, parse_err: ParseErr) {
-        if token.ty == TokenType::Eof {
-            self.report(token.line, " at end", &parse_err.to_string());
+        let location = if token.ty == TokenType::Eof {
+            " at end".to_owned()
         } else {
-            self.report(
-                token.line,
-                &format!(" at '{}'", token.lexeme),
-                &parse_err.to_string(),
+            format!(" at '{}'", token.lexeme)
+        };
+        let message = parse_err.to_string();
+        self.record(
+            Severity::Error,
+            token.line,
+            Some(token.column),
+            token.lexeme.len(),
+            &message,
+        );
+        if !self.quiet {
+            println!("[line {}] Error{}: {}", token.line, location, message);
+        }
+        self.had_error = true;
+    }
+
+    /* Report a problem that does not stop the program from running. */
+    pub fn warning(&mut self, token: &Token, message: &str) {
+        self.record(
+            Severity::Warning,
+            token.line,
+            Some(token.column),
+            token.lexeme.len(),
+            message,
+        );
+        // Warnings go to stderr so they don't mix with the program's own output.
+        if !self.quiet {
+            eprintln!(
+                "[line {}] Warning at '{}': {}",
+                token.line, token.lexeme, message
             );
         }
     }
 
+    /* Print the error followed by a stack trace, innermost frame first. Until
+     * the language has functions the only frame is the top-level script. */
     pub fn runtime_error(&mut self, token: &Token, interp_err: InterpErr) {
-        println!("{}\n[line {}]", interp_err, token.line);
+        println!("{}\n[line {}] in script", interp_err, token.line);
         self.had_runtime_error = true;
     }
+
+    fn record(
+        &mut self,
+        severity: Severity,
+        line: usize,
+        column: Option<usize>,
+        length: usize,
+        message: &str,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            line,
+            column,
+            length,
+            message: message.to_owned(),
+        });
+    }
 }
@@ -3,6 +3,7 @@ use crate::{
     error_handler::ErrorHandler,
     expr::Expr,
     stmt::Stmt,
+    suggest::did_you_mean,
     token::{Literal, Token},
     token_type::TokenType,
 };
@@ -16,8 +17,17 @@ pub enum InterpErr {
     OpsMustBeNums,
     #[error("Operands must be two numbers or two strings.")]
     OpsMustBeNumsOrStrs,
-    #[error("Undefined variable '{0}'.")]
-    UndefVar(String),
+    #[error("Undefined variable '{0}'.{}", did_you_mean_hint(.1))]
+    UndefVar(String, Option<Box<str>>),
+    #[error("Variable '{0}' is used before being assigned.")]
+    UnassignedVar(String),
+}
+
+fn did_you_mean_hint(suggestion: &Option<Box<str>>) -> String {
+    match suggestion {
+        Some(name) => format!(" Did you mean '{}'?", name),
+        None => String::new(),
+    }
 }
 
 type InterpResult = Result<Literal, (Token, InterpErr)>;
@@ -26,6 +36,12 @@ pub struct Interpreter {
     environment: Environment,
 }
 
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Interpreter {
     pub fn new() -> Interpreter {
         Interpreter {
@@ -35,9 +51,8 @@ impl Interpreter {
 
     pub fn interpret(&mut self, statements: Vec<Stmt>, error_handler: &mut ErrorHandler) {
         for statement in statements {
-            match self.execute_stmt(statement) {
-                Err((token, err)) => error_handler.runtime_error(&token, err),
-                _ => (),
+            if let Err((token, err)) = self.execute_stmt(statement) {
+                error_handler.runtime_error(&token, err)
             }
         }
     }
@@ -49,26 +64,25 @@ impl Interpreter {
                 let val = self.evaluate_expr(expr);
                 match val {
                     Ok(ref lit) => {
-                        println!("{}", lit.to_string());
+                        println!("{}", lit);
                         val
                     }
                     _ => val,
                 }
             }
             Stmt::Var(token, initializer) => {
-                if let Some(initializer) = initializer {
-                    let value = self.evaluate_expr(initializer)?;
-                    self.environment.define(token.lexeme, value);
-                } else {
-                    self.environment.define(token.lexeme, Literal::Nil);
-                }
+                let value = match initializer {
+                    Some(initializer) => Some(self.evaluate_expr(initializer)?),
+                    None => None,
+                };
+                self.environment.define(token.lexeme, value);
                 Ok(Literal::Nil)
-            },
+            }
             Stmt::Block(statements) => self.execute_block(
                 statements,
                 Environment::new_enclosing(Box::new(self.environment.clone())),
             ),
-            Stmt::If(cond, if_branch, else_branch) => {
+            Stmt::If(_, cond, if_branch, else_branch) => {
                 if Self::is_truthy(&self.evaluate_expr(cond)?) {
                     self.execute_stmt(*if_branch)
                 } else if let Some(eb) = else_branch {
@@ -76,8 +90,8 @@ impl Interpreter {
                 } else {
                     Ok(Literal::Nil)
                 }
-            },
-            Stmt::While(cond, body) => {
+            }
+            Stmt::While(_, cond, body) => {
                 while Self::is_truthy(&self.evaluate_expr(cond.clone())?) {
                     self.execute_stmt(*body.clone())?;
                 }
@@ -128,21 +142,24 @@ impl Interpreter {
                     _ => Ok(Literal::Nil),
                 }
             }
-            Expr::Variable(token) => {
-                if let Some(value) = self.environment.get(&token) {
-                    Ok(value.clone())
-                } else {
+            Expr::Variable(token) => match self.environment.get(&token) {
+                Some(Some(value)) => Ok(value.clone()),
+                Some(None) => {
                     let s = token.lexeme.to_owned();
-                    Err((token, InterpErr::UndefVar(s)))
+                    Err((token, InterpErr::UnassignedVar(s)))
                 }
-            }
+                None => {
+                    let err = self.undefined_variable(&token);
+                    Err((token, err))
+                }
+            },
             Expr::Assign(name, value) => {
                 let value = self.evaluate_expr(*value)?;
                 if self.environment.assign(&name, &value) {
-                    Ok(self.environment.get(&name).unwrap().clone())
+                    Ok(value)
                 } else {
-                    let s = name.lexeme.clone();
-                    Err((name, InterpErr::UndefVar(s)))
+                    let err = self.undefined_variable(&name);
+                    Err((name, err))
                 }
             }
             Expr::Logical(left, op, right) => {
@@ -166,7 +183,12 @@ impl Interpreter {
         Ok(Literal::Nil)
     }
 
-    fn is_truthy(lit: &Literal) -> bool {
+    fn undefined_variable(&self, name: &Token) -> InterpErr {
+        let suggestion = did_you_mean(&name.lexeme, self.environment.names());
+        InterpErr::UndefVar(name.lexeme.clone(), suggestion.map(Box::from))
+    }
+
+    pub(crate) fn is_truthy(lit: &Literal) -> bool {
         match lit {
             Literal::Nil => false,
             Literal::Bool(val) => val.to_owned(),
@@ -2,8 +2,12 @@ pub mod environment;
 pub mod error_handler;
 pub mod expr;
 pub mod interpreter;
+pub mod lint;
+pub mod lsp;
 pub mod parser;
+pub mod resolver;
 pub mod scanner;
 pub mod stmt;
+pub mod suggest;
 pub mod token;
 pub mod token_type;
@@ -0,0 +1,147 @@
+use crate::{
+    error_handler::ErrorHandler,
+    expr::Expr,
+    interpreter::Interpreter,
+    resolver::{Resolution, Resolver},
+    stmt::Stmt,
+    token::Token,
+};
+
+/* Which lints to run. Every lint is enabled by default. */
+#[derive(Debug, Clone, Copy)]
+pub struct LintConfig {
+    pub unused_variables: bool,
+    pub shadowing: bool,
+    pub constant_conditions: bool,
+    pub unassigned_reads: bool,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        LintConfig {
+            unused_variables: true,
+            shadowing: true,
+            constant_conditions: true,
+            unassigned_reads: true,
+        }
+    }
+}
+
+impl LintConfig {
+    pub const NAMES: [&'static str; 4] = [
+        "unused-variables",
+        "shadowing",
+        "constant-conditions",
+        "unassigned-reads",
+    ];
+
+    /* Disable the lint with the given name. Returns false if there is no such lint. */
+    pub fn allow(&mut self, name: &str) -> bool {
+        let lint = match name {
+            "unused-variables" => &mut self.unused_variables,
+            "shadowing" => &mut self.shadowing,
+            "constant-conditions" => &mut self.constant_conditions,
+            "unassigned-reads" => &mut self.unassigned_reads,
+            _ => return false,
+        };
+        *lint = false;
+        true
+    }
+}
+
+/* Report warnings for suspicious but valid code. */
+pub fn lint(statements: &[Stmt], config: &LintConfig, error_handler: &mut ErrorHandler) {
+    let resolution = Resolver::new().resolve(statements);
+    let mut warnings = Vec::new();
+
+    if config.unused_variables {
+        unused_variables(&resolution, &mut warnings);
+    }
+    if config.shadowing {
+        shadowing(&resolution, &mut warnings);
+    }
+    if config.unassigned_reads {
+        unassigned_reads(&resolution, &mut warnings);
+    }
+    if config.constant_conditions {
+        for statement in statements {
+            constant_conditions(statement, &mut warnings);
+        }
+    }
+
+    warnings.sort_by_key(|(token, _)| (token.line, token.column));
+    for (token, message) in warnings {
+        error_handler.warning(&token, &message);
+    }
+}
+
+fn unused_variables(resolution: &Resolution, warnings: &mut Vec<(Token, String)>) {
+    for (index, declaration) in resolution.declarations.iter().enumerate() {
+        if !resolution.is_read(index) {
+            let name = &declaration.name;
+            warnings.push((
+                name.clone(),
+                format!("Variable '{}' is never read.", name.lexeme),
+            ));
+        }
+    }
+}
+
+fn shadowing(resolution: &Resolution, warnings: &mut Vec<(Token, String)>) {
+    for declaration in &resolution.declarations {
+        if let Some(shadowed) = declaration.shadows {
+            let name = &declaration.name;
+            warnings.push((
+                name.clone(),
+                format!(
+                    "Variable '{}' shadows a variable declared on line {}.",
+                    name.lexeme, resolution.declarations[shadowed].name.line
+                ),
+            ));
+        }
+    }
+}
+
+fn unassigned_reads(resolution: &Resolution, warnings: &mut Vec<(Token, String)>) {
+    for &read in &resolution.unassigned_reads {
+        let name = &resolution.references[read].name;
+        warnings.push((
+            name.clone(),
+            format!("Variable '{}' is read before being assigned.", name.lexeme),
+        ));
+    }
+}
+
+fn constant_conditions(stmt: &Stmt, warnings: &mut Vec<(Token, String)>) {
+    match stmt {
+        Stmt::ExprStmt(_) | Stmt::PrintStmt(_) | Stmt::Var(_, _) => (),
+        Stmt::Block(statements) => {
+            for statement in statements {
+                constant_conditions(statement, warnings);
+            }
+        }
+        Stmt::If(keyword, cond, if_branch, else_branch) => {
+            check_condition(keyword, cond, warnings);
+            constant_conditions(if_branch, warnings);
+            if let Some(else_branch) = else_branch {
+                constant_conditions(else_branch, warnings);
+            }
+        }
+        Stmt::While(keyword, cond, body) => {
+            check_condition(keyword, cond, warnings);
+            constant_conditions(body, warnings);
+        }
+    }
+}
+
+fn check_condition(keyword: &Token, cond: &Expr, warnings: &mut Vec<(Token, String)>) {
+    let mut cond = cond;
+    while let Expr::Grouping(inner) = cond {
+        cond = inner;
+    }
+
+    if let Expr::Literal(literal) = cond {
+        let truthy = Interpreter::is_truthy(literal);
+        warnings.push((keyword.clone(), format!("Condition is always {}.", truthy)));
+    }
+}
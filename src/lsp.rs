@@ -0,0 +1,335 @@
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    error_handler::{Diagnostic, ErrorHandler, Severity},
+    lint::{lint, LintConfig},
+    parser::Parser,
+    resolver::{Resolution, Resolver},
+    scanner::Scanner,
+    token::Token,
+};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_REQUEST: i64 = -32600;
+
+/* Everything the server knows about one open document. */
+struct Document {
+    text: String,
+    resolution: Resolution,
+}
+
+impl Document {
+    fn analyze(text: String) -> (Document, Vec<Diagnostic>) {
+        let mut error_handler = ErrorHandler::quiet();
+        let mut scanner = Scanner::new(text.clone(), &mut error_handler);
+        let tokens = scanner.scan_tokens().to_owned();
+        let mut parser = Parser::new(tokens);
+        let statements = parser.parse_recovering(&mut error_handler);
+        lint(&statements, &LintConfig::default(), &mut error_handler);
+        let resolution = Resolver::new().resolve(&statements);
+
+        let diagnostics = error_handler.diagnostics().to_vec();
+        (Document { text, resolution }, diagnostics)
+    }
+
+    fn source_line(&self, line: usize) -> &str {
+        self.text.lines().nth(line - 1).unwrap_or("")
+    }
+
+    /* LSP counts columns in UTF-16 code units, while tokens count bytes.
+     * Both columns here are one-based, and columns past the end of the line
+     * count one unit per byte. */
+    fn utf16_column(&self, line: usize, byte_column: usize) -> usize {
+        let text = self.source_line(line);
+        let units: usize = text
+            .char_indices()
+            .take_while(|&(offset, _)| offset < byte_column - 1)
+            .map(|(_, c)| c.len_utf16())
+            .sum();
+        units + (byte_column - 1).saturating_sub(text.len()) + 1
+    }
+
+    fn byte_column(&self, line: usize, utf16_column: usize) -> usize {
+        let text = self.source_line(line);
+        let mut units = 0;
+        for (offset, c) in text.char_indices() {
+            if units >= utf16_column - 1 {
+                return offset + 1;
+            }
+            units += c.len_utf16();
+        }
+        text.len() + (utf16_column - 1).saturating_sub(units) + 1
+    }
+
+    fn position(&self, line: usize, byte_column: usize) -> Value {
+        json!({ "line": line - 1, "character": self.utf16_column(line, byte_column) - 1 })
+    }
+
+    fn token_range(&self, token: &Token) -> Value {
+        json!({
+            "start": self.position(token.line, token.column),
+            "end": self.position(token.line, token.column + token.lexeme.len()),
+        })
+    }
+}
+
+/* A Language Server Protocol server speaking JSON-RPC over a pair of streams. */
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    /* Serve requests until the client sends `exit` or closes the input.
+     * Returns whether the client shut the server down cleanly. Only failing
+     * to read or write the streams is an error; a malformed message gets an
+     * error response and the server carries on. */
+    pub fn run(&mut self, mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+        while let Some(message) = read_message(&mut input)? {
+            let message = match message {
+                Ok(message) => message,
+                Err(error) => {
+                    // There is no telling which request it was, so no id.
+                    let response = error_response(&Value::Null, PARSE_ERROR, &error);
+                    write_message(&mut output, &response)?;
+                    continue;
+                }
+            };
+            let Some(method) = message.get("method").and_then(Value::as_str) else {
+                let response = error_response(
+                    &Value::Null,
+                    INVALID_REQUEST,
+                    "Expected an object with a method.",
+                );
+                write_message(&mut output, &response)?;
+                continue;
+            };
+            if method == "exit" {
+                return Ok(self.shutdown);
+            }
+
+            let params = &message["params"];
+            match message.get("id") {
+                Some(id) => {
+                    let response = match self.handle_request(method, params) {
+                        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                        Err((code, error)) => error_response(id, code, &error),
+                    };
+                    write_message(&mut output, &response)?;
+                }
+                None => {
+                    for notification in self.handle_notification(method, params) {
+                        write_message(&mut output, &notification)?;
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        if self.shutdown {
+            return Err((INVALID_REQUEST, "Server is shutting down.".to_owned()));
+        }
+
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rlox-lsp" },
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            }
+            "textDocument/definition" => Ok(self.definition(params)),
+            "textDocument/hover" => Ok(self.hover(params)),
+            "textDocument/documentSymbol" => Ok(self.document_symbols(params)),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'.", method))),
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .unwrap_or("")
+            .to_owned();
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Only full document sync is advertised, so the last change is the whole text.
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        };
+
+        match text {
+            Some(text) => {
+                let (document, diagnostics) = Document::analyze(text.to_owned());
+                let diagnostics = diagnostics
+                    .iter()
+                    .map(|d| diagnostic_to_json(d, &document))
+                    .collect();
+                self.documents.insert(uri.clone(), document);
+                vec![publish_diagnostics(&uri, diagnostics)]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /* The open document and the declaration under the cursor in `params`. */
+    fn lookup<'a>(&'a self, params: &'a Value) -> Option<(&'a str, &'a Document, &'a Token)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let line = params["position"]["line"].as_u64()? as usize + 1;
+        let column = params["position"]["character"].as_u64()? as usize + 1;
+        let column = document.byte_column(line, column);
+        let declaration = document.resolution.declaration_at(line, column)?;
+        Some((uri, document, &declaration.name))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        match self.lookup(params) {
+            Some((uri, document, name)) => {
+                json!({ "uri": uri, "range": document.token_range(name) })
+            }
+            None => Value::Null,
+        }
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        match self.lookup(params) {
+            Some((_, document, name)) => json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!(
+                        "```lox\n{}\n```\nDeclared on line {}.",
+                        document.source_line(name.line).trim(),
+                        name.line
+                    ),
+                },
+            }),
+            None => Value::Null,
+        }
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let Some(document) = self.documents.get(uri) else {
+            return Value::Null;
+        };
+
+        let symbols = document
+            .resolution
+            .declarations
+            .iter()
+            .map(|declaration| {
+                json!({
+                    "name": declaration.name.lexeme,
+                    "kind": 13,
+                    "location": { "uri": uri, "range": document.token_range(&declaration.name) },
+                })
+            })
+            .collect();
+        Value::Array(symbols)
+    }
+}
+
+fn diagnostic_to_json(diagnostic: &Diagnostic, document: &Document) -> Value {
+    // Errors without a column cover the whole line.
+    let (start, end) = match diagnostic.column {
+        Some(column) => (column, column + diagnostic.length),
+        None => (1, document.source_line(diagnostic.line).len() + 1),
+    };
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2,
+    };
+    json!({
+        "range": {
+            "start": document.position(diagnostic.line, start),
+            "end": document.position(diagnostic.line, end),
+        },
+        "severity": severity,
+        "source": "rlox",
+        "message": diagnostic.message,
+    })
+}
+
+fn error_response(id: &Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/* The next message, or None at the end of the input. A message that can't
+ * be parsed is read past and returned as an error message instead. */
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, String>>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(length) = header.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse::<usize>().ok();
+        }
+    }
+
+    let Some(content_length) = content_length else {
+        return Ok(Some(Err("Missing Content-Length header.".to_owned())));
+    };
+    let mut body = vec![0; content_length];
+    input.read_exact(&mut body)?;
+    Ok(Some(
+        serde_json::from_slice(&body).map_err(|error| format!("Parse error: {}.", error)),
+    ))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
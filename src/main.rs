@@ -1,7 +1,11 @@
 use std::{io::Write, path::Path};
 
 use rlox::{
-    error_handler::ErrorHandler, interpreter::Interpreter, parser::Parser, scanner::Scanner,
+    error_handler::ErrorHandler,
+    interpreter::Interpreter,
+    lint::{lint, LintConfig},
+    parser::Parser,
+    scanner::Scanner,
 };
 
 const USAGE_ERROR: i32 = 64;
@@ -9,24 +13,40 @@ const DATA_ERROR: i32 = 65;
 const SOFTWARE_ERROR: i32 = 70;
 
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
+    let mut lints = LintConfig::default();
+    let mut args = Vec::new();
+    for arg in std::env::args() {
+        match arg.strip_prefix("--allow=") {
+            Some(name) if !lints.allow(name) => {
+                println!(
+                    "Unknown lint '{}'. Expected one of: {}.",
+                    name,
+                    LintConfig::NAMES.join(", ")
+                );
+                std::process::exit(USAGE_ERROR)
+            }
+            Some(_) => (),
+            None => args.push(arg),
+        }
+    }
+
     match args.len() {
         1 => run_prompt().unwrap(),
-        2 => run_file(&args[1]).unwrap(),
+        2 => run_file(&args[1], &lints).unwrap(),
         _ => {
-            println!("Usage: rlox [script]");
+            println!("Usage: rlox [--allow=<lint>]... [script]");
             std::process::exit(USAGE_ERROR)
         }
     }
 }
 
-fn run_file(path: impl AsRef<Path>) -> std::io::Result<()> {
+fn run_file(path: impl AsRef<Path>, lints: &LintConfig) -> std::io::Result<()> {
     let script = std::fs::read_to_string(path)?;
 
     let mut interpreter = Interpreter::new();
     let mut error_handler = ErrorHandler::new();
 
-    run(script, &mut interpreter, &mut error_handler);
+    run(script, &mut interpreter, &mut error_handler, Some(lints));
 
     if error_handler.had_error() {
         std::process::exit(DATA_ERROR);
@@ -50,19 +70,28 @@ fn run_prompt() -> std::io::Result<()> {
         if line.is_empty() {
             break;
         }
-        run(line, &mut interpreter, &mut error_handler);
+        // Each line is its own program, so lints like unused variables would only be noise.
+        run(line, &mut interpreter, &mut error_handler, None);
     }
 
     Ok(())
 }
 
-fn run(source: String, interpreter: &mut Interpreter, error_handler: &mut ErrorHandler) {
+fn run(
+    source: String,
+    interpreter: &mut Interpreter,
+    error_handler: &mut ErrorHandler,
+    lints: Option<&LintConfig>,
+) {
     let mut scanner = Scanner::new(source, error_handler);
     let tokens = scanner.scan_tokens().to_owned();
     let mut parser = Parser::new(tokens.to_owned());
     let statements = parser.parse(error_handler);
 
     if let Some(statements) = statements {
+        if let Some(lints) = lints {
+            lint(&statements, lints, error_handler);
+        }
         interpreter.interpret(statements, error_handler);
     }
 }
@@ -10,6 +10,7 @@ use thiserror::Error;
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
+    errors: Vec<(Token, ParseErr)>,
 }
 
 #[derive(Debug, Clone, Copy, Error)]
@@ -20,8 +21,6 @@ pub enum ParseErr {
     ExpectExpr,
     #[error("Expect ';' after value.")]
     MissingSemicolonAfterExprStmt,
-    #[error("Sync error.")]
-    Sync,
     #[error("Expect variable name.")]
     ExpectVarName,
     #[error("Invalid assignment target.")]
@@ -41,36 +40,49 @@ pub enum ParseErr {
     #[error("Expect ';' after for condition.")]
     ExpectSemicolonAfterForCond,
     #[error("Expect ')' after for clauses.")]
-    ExpectRightParenAfterForClause
+    ExpectRightParenAfterForClause,
 }
 
 type ParseResult<T> = Result<T, (Token, ParseErr)>;
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
-        Parser { tokens, current: 0 }
+        Parser {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+        }
     }
 
     pub fn parse(&mut self, error_handler: &mut ErrorHandler) -> Option<Vec<Stmt>> {
+        let statements = self.parse_recovering(error_handler);
+        if self.errors.is_empty() {
+            Some(statements)
+        } else {
+            None
+        }
+    }
+
+    /* Parse as much as possible, reporting every error and returning the
+     * statements that parsed cleanly. */
+    pub fn parse_recovering(&mut self, error_handler: &mut ErrorHandler) -> Vec<Stmt> {
         let mut statements = Vec::new();
         while !self.is_at_end() {
-            let stmt = self.declaration();
-            match stmt {
-                Ok(stmt) => statements.push(stmt),
-                Err((token, parse_err)) => {
-                    error_handler.parse_error(&token, parse_err);
-                    return None;
-                }
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
             }
         }
-        Some(statements)
+        for (token, parse_err) in &self.errors {
+            error_handler.parse_error(token, *parse_err);
+        }
+        statements
     }
 
     fn expression(&mut self) -> ParseResult<Box<Expr>> {
         self.assignment()
     }
 
-    fn declaration(&mut self) -> ParseResult<Stmt> {
+    fn declaration(&mut self) -> Option<Stmt> {
         let res = {
             if self.matches(vec![TokenType::Var]) {
                 self.var_declaration()
@@ -79,11 +91,13 @@ impl Parser {
             }
         };
 
-        if res.is_err() {
-            self.synchronize();
-            self.error(ParseErr::Sync)
-        } else {
-            res
+        match res {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.synchronize();
+                None
+            }
         }
     }
 
@@ -105,21 +119,20 @@ impl Parser {
         }
 
         if self.matches(vec![TokenType::LeftBrace]) {
-            return self.block().map(|stmts| Stmt::Block(stmts));
+            return self.block().map(Stmt::Block);
         }
 
         self.expression_statement()
     }
 
     fn for_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
         self.consume_or_err(TokenType::LeftParen, ParseErr::ExpectLeftParenAfterFor)?;
 
         let mut initializer = None;
-        if self.matches1(TokenType::Semicolon) {
-            ()
-        } else if self.matches1(TokenType::Var) {
+        if self.matches1(TokenType::Var) {
             initializer = Some(self.var_declaration()?);
-        } else {
+        } else if !self.matches1(TokenType::Semicolon) {
             initializer = Some(self.expression_statement()?);
         }
 
@@ -133,7 +146,10 @@ impl Parser {
         if !self.check(TokenType::RightParen) {
             increment = Some(self.expression()?);
         }
-        self.consume_or_err(TokenType::RightParen, ParseErr::ExpectRightParenAfterForClause)?;
+        self.consume_or_err(
+            TokenType::RightParen,
+            ParseErr::ExpectRightParenAfterForClause,
+        )?;
 
         let mut body = self.statement()?;
 
@@ -144,7 +160,7 @@ impl Parser {
         if condition.is_none() {
             condition = Some(Expr::Literal(Literal::Bool(true)));
         }
-        body = Stmt::While(condition.unwrap(), Box::new(body));
+        body = Stmt::While(keyword, condition.unwrap(), Box::new(body));
 
         if let Some(initializer) = initializer {
             body = Stmt::Block(vec![initializer, body]);
@@ -154,6 +170,7 @@ impl Parser {
     }
 
     fn if_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
         self.consume_or_err(TokenType::LeftParen, ParseErr::ExpectLeftParenAfterIf)?;
 
         let cond = self.expression()?;
@@ -168,11 +185,11 @@ impl Parser {
             None
         };
 
-        Ok(Stmt::If(*cond, if_branch, else_branch))
+        Ok(Stmt::If(keyword, *cond, if_branch, else_branch))
     }
 
     fn print_statement(&mut self) -> ParseResult<Stmt> {
-        let value = self.expression().map_err(|e| e)?;
+        let value = self.expression()?;
         if self.consume(TokenType::Semicolon).is_some() {
             Ok(Stmt::PrintStmt(*value))
         } else {
@@ -185,12 +202,12 @@ impl Parser {
         match name {
             Some(name) => {
                 let name = name.clone();
-                let mut initializer = Box::new(Expr::Literal(Literal::Nil));
+                let mut initializer = None;
                 if self.matches(vec![TokenType::Equal]) {
-                    initializer = self.expression()?;
+                    initializer = Some(*self.expression()?);
                 }
                 if self.consume(TokenType::Semicolon).is_some() {
-                    Ok(Stmt::Var(name, Some(*initializer)))
+                    Ok(Stmt::Var(name, initializer))
                 } else {
                     self.error(ParseErr::MissingSemicolonAfterExprStmt)
                 }
@@ -200,19 +217,23 @@ impl Parser {
     }
 
     fn while_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous().clone();
         self.consume_or_err(TokenType::LeftParen, ParseErr::ExpectLeftParenAfterWhile)?;
 
         let cond = self.expression()?;
 
-        self.consume_or_err(TokenType::RightParen, ParseErr::ExpectRightParenAfterWhileCond)?;
+        self.consume_or_err(
+            TokenType::RightParen,
+            ParseErr::ExpectRightParenAfterWhileCond,
+        )?;
 
         let body = Box::new(self.statement()?);
 
-        Ok(Stmt::While(*cond, body))
+        Ok(Stmt::While(keyword, *cond, body))
     }
 
     fn expression_statement(&mut self) -> ParseResult<Stmt> {
-        let value = self.expression().map_err(|e| e)?;
+        let value = self.expression()?;
         if self.consume(TokenType::Semicolon).is_some() {
             Ok(Stmt::ExprStmt(*value))
         } else {
@@ -223,7 +244,9 @@ impl Parser {
     fn block(&mut self) -> ParseResult<Vec<Stmt>> {
         let mut statements = Vec::new();
         while !self.check(TokenType::RightBrace) && !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.declaration() {
+                statements.push(stmt);
+            }
         }
         if self.consume(TokenType::RightBrace).is_none() {
             self.error(ParseErr::ExpectRightBraceAfterBlock)
@@ -422,7 +445,6 @@ impl Parser {
     }
 
     fn synchronize(&mut self) {
-        dbg!(&self.tokens);
         self.advance();
 
         while !self.is_at_end() {
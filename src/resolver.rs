@@ -0,0 +1,212 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{expr::Expr, stmt::Stmt, token::Token};
+
+/* A variable introduced by a `var` statement. */
+#[derive(Debug, Clone)]
+pub struct Declaration {
+    pub name: Token,
+    /* Number of blocks enclosing the declaration; globals are at depth 0. */
+    pub depth: usize,
+    /* The declaration in an enclosing scope that this one hides. */
+    pub shadows: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+/* A use of a variable, linked to the declaration it refers to if any is in scope. */
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: Token,
+    pub declaration: Option<usize>,
+    pub access: Access,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Resolution {
+    pub declarations: Vec<Declaration>,
+    pub references: Vec<Reference>,
+    /* Reads (indices into `references`) of variables that cannot have been
+     * assigned on any path reaching them. */
+    pub unassigned_reads: Vec<usize>,
+}
+
+impl Resolution {
+    /* Find the declaration named by the token at the given one-based line
+     * and byte column, whether the token is the declaration itself or a
+     * reference to it. */
+    pub fn declaration_at(&self, line: usize, column: usize) -> Option<&Declaration> {
+        let covers = |token: &Token| {
+            token.line == line
+                && token.column <= column
+                && column <= token.column + token.lexeme.len()
+        };
+
+        if let Some(declaration) = self.declarations.iter().find(|d| covers(&d.name)) {
+            return Some(declaration);
+        }
+        self.references
+            .iter()
+            .find(|r| covers(&r.name))
+            .and_then(|r| r.declaration)
+            .map(|index| &self.declarations[index])
+    }
+
+    pub fn is_read(&self, declaration: usize) -> bool {
+        self.references
+            .iter()
+            .any(|r| r.declaration == Some(declaration) && r.access == Access::Read)
+    }
+}
+
+/* Statically links every variable use to its declaration, mirroring the
+ * scoping rules the interpreter applies at runtime. */
+pub struct Resolver {
+    scopes: Vec<HashMap<String, usize>>,
+    /* Declarations that may have been assigned on some path to the current point. */
+    assigned: HashSet<usize>,
+    resolution: Resolution,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: vec![HashMap::new()],
+            assigned: HashSet::new(),
+            resolution: Resolution::default(),
+        }
+    }
+
+    pub fn resolve(mut self, statements: &[Stmt]) -> Resolution {
+        for statement in statements {
+            self.resolve_stmt(statement);
+        }
+        self.resolution
+    }
+
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::ExprStmt(expr) | Stmt::PrintStmt(expr) => self.resolve_expr(expr),
+            Stmt::Var(name, initializer) => {
+                // The initializer sees the enclosing binding, not the new one.
+                if let Some(initializer) = initializer {
+                    self.resolve_expr(initializer);
+                }
+                let declaration = self.declare(name);
+                if initializer.is_some() {
+                    self.assigned.insert(declaration);
+                }
+            }
+            Stmt::Block(statements) => {
+                self.scopes.push(HashMap::new());
+                for statement in statements {
+                    self.resolve_stmt(statement);
+                }
+                self.scopes.pop();
+            }
+            Stmt::If(_, cond, if_branch, else_branch) => {
+                self.resolve_expr(cond);
+                let before = self.assigned.clone();
+                self.resolve_stmt(if_branch);
+                if let Some(else_branch) = else_branch {
+                    // The else branch can't see assignments made in the if branch.
+                    let after_if = std::mem::replace(&mut self.assigned, before);
+                    self.resolve_stmt(else_branch);
+                    self.assigned.extend(after_if);
+                }
+            }
+            Stmt::While(_, cond, body) => {
+                let first_read = self.resolution.unassigned_reads.len();
+                self.resolve_expr(cond);
+                self.resolve_stmt(body);
+                // A read early in the loop may see an assignment made later in a
+                // previous iteration.
+                let loop_reads = self.resolution.unassigned_reads.split_off(first_read);
+                for read in loop_reads {
+                    let declaration = self.resolution.references[read].declaration;
+                    if !declaration.is_some_and(|d| self.assigned.contains(&d)) {
+                        self.resolution.unassigned_reads.push(read);
+                    }
+                }
+            }
+        }
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_) => (),
+            Expr::Grouping(expression) => self.resolve_expr(expression),
+            Expr::Unary(_, right) => self.resolve_expr(right),
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Variable(name) => self.reference(name, Access::Read),
+            Expr::Assign(name, value) => {
+                self.resolve_expr(value);
+                self.reference(name, Access::Write);
+            }
+        }
+    }
+
+    fn declare(&mut self, name: &Token) -> usize {
+        let index = self.resolution.declarations.len();
+        let (scope, enclosing) = self.scopes.split_last().expect("global scope");
+        let shadows = if scope.contains_key(&name.lexeme) {
+            None
+        } else {
+            enclosing
+                .iter()
+                .rev()
+                .find_map(|scope| scope.get(&name.lexeme).copied())
+        };
+        self.resolution.declarations.push(Declaration {
+            name: name.clone(),
+            depth: self.scopes.len() - 1,
+            shadows,
+        });
+        self.scopes
+            .last_mut()
+            .expect("global scope")
+            .insert(name.lexeme.clone(), index);
+        index
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn reference(&mut self, name: &Token, access: Access) {
+        let declaration = self.lookup(&name.lexeme);
+        if let Some(declaration) = declaration {
+            match access {
+                Access::Read if !self.assigned.contains(&declaration) => self
+                    .resolution
+                    .unassigned_reads
+                    .push(self.resolution.references.len()),
+                Access::Write => {
+                    self.assigned.insert(declaration);
+                }
+                Access::Read => (),
+            }
+        }
+        self.resolution.references.push(Reference {
+            name: name.clone(),
+            declaration,
+            access,
+        });
+    }
+}
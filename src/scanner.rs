@@ -12,12 +12,14 @@ pub struct Scanner<'a> {
     start: usize,
     current: usize,
     line: usize,
+    /* Offset of the first character on the current line. */
+    line_start: usize,
     keywords: HashMap<&'static str, TokenType>,
     error_handler: &'a mut ErrorHandler,
 }
 
 impl<'a> Scanner<'a> {
-    pub fn new(source: String, error_handler: &mut ErrorHandler) -> Scanner {
+    pub fn new(source: String, error_handler: &mut ErrorHandler) -> Scanner<'_> {
         let mut keywords = HashMap::new();
         keywords.insert("and", TokenType::And);
         keywords.insert("class", TokenType::Class);
@@ -42,6 +44,7 @@ impl<'a> Scanner<'a> {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
             keywords,
             error_handler,
         }
@@ -53,8 +56,13 @@ impl<'a> Scanner<'a> {
             self.scan_token();
         }
 
-        self.tokens
-            .push(Token::new(TokenType::Eof, "".to_owned(), None, self.line));
+        self.tokens.push(Token::new(
+            TokenType::Eof,
+            "".to_owned(),
+            None,
+            self.line,
+            self.column(self.current),
+        ));
 
         &self.tokens
     }
@@ -110,7 +118,10 @@ impl<'a> Scanner<'a> {
                 }
             }
             ' ' | '\r' | '\t' => (),
-            '\n' => self.line += 1,
+            '\n' => {
+                self.line += 1;
+                self.line_start = self.current;
+            }
             '"' => self.string(),
             c if self.is_digit(c) => self.number(),
             c if self.is_alpha(c) => self.identifier(),
@@ -127,7 +138,7 @@ impl<'a> Scanner<'a> {
 
         let text = &self.source[self.start..self.current];
         if let Some(token_type) = self.keywords.get(text) {
-            self.add_token(token_type.clone());
+            self.add_token(*token_type);
         } else {
             self.add_token(TokenType::Identifier);
         }
@@ -156,6 +167,7 @@ impl<'a> Scanner<'a> {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
@@ -188,7 +200,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_digit(&self, c: char) -> bool {
-        ('0'..='9').contains(&c)
+        c.is_ascii_digit()
     }
 
     fn peek_next(&mut self) -> char {
@@ -200,7 +212,7 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_alpha(&self, c: char) -> bool {
-        ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || c == '_'
+        c.is_ascii_lowercase() || c.is_ascii_uppercase() || c == '_'
     }
 
     fn is_alphanumeric(&self, c: char) -> bool {
@@ -217,14 +229,22 @@ impl<'a> Scanner<'a> {
         c as char
     }
 
+    /* One-based column of the given source offset on the current line. */
+    fn column(&self, offset: usize) -> usize {
+        offset.saturating_sub(self.line_start) + 1
+    }
+
     fn add_token(&mut self, ty: TokenType) {
         let text = self.source[self.start..self.current].to_owned();
-        self.tokens.push(Token::new(ty, text, None, self.line))
+        let column = self.column(self.start);
+        self.tokens
+            .push(Token::new(ty, text, None, self.line, column))
     }
 
     fn add_token_lit(&mut self, ty: TokenType, literal: Literal) {
         let text = self.source[self.start..self.current].to_owned();
+        let column = self.column(self.start);
         self.tokens
-            .push(Token::new(ty, text, Some(literal), self.line))
+            .push(Token::new(ty, text, Some(literal), self.line, column))
     }
 }
@@ -5,7 +5,7 @@ pub enum Stmt {
     ExprStmt(Expr),
     PrintStmt(Expr),
     Var(Token, Option<Expr>),
-    While(Expr, Box<Stmt>),
+    While(Token, Expr, Box<Stmt>),
     Block(Vec<Stmt>),
-    If(Expr, Box<Stmt>, Option<Box<Stmt>>),
+    If(Token, Expr, Box<Stmt>, Option<Box<Stmt>>),
 }
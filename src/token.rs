@@ -7,15 +7,23 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<Literal>,
     pub line: usize,
+    pub column: usize,
 }
 
 impl Token {
-    pub fn new(ty: TokenType, lexeme: String, literal: Option<Literal>, line: usize) -> Token {
+    pub fn new(
+        ty: TokenType,
+        lexeme: String,
+        literal: Option<Literal>,
+        line: usize,
+        column: usize,
+    ) -> Token {
         Token {
             ty,
             lexeme,
             literal,
             line,
+            column,
         }
     }
 }
@@ -35,20 +43,20 @@ pub enum Literal {
     Nil,
 }
 
-impl ToString for Literal {
-    fn to_string(&self) -> String {
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use Literal::*;
         match self {
-            Id(s) | Str(s) => s.to_owned(),
-            Bool(b) => b.to_string(),
+            Id(s) | Str(s) => write!(f, "{}", s),
+            Bool(b) => write!(f, "{}", b),
             Double(n) => {
                 let mut text = n.to_string();
                 if text.ends_with(".0") {
                     text = text[0..text.len() - 2].to_owned();
                 }
-                text
+                write!(f, "{}", text)
             }
-            Nil => "nil".to_owned(),
+            Nil => write!(f, "nil"),
         }
     }
 }
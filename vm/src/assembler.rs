@@ -0,0 +1,593 @@
+/* Reads bytecode written as text back into functions. The syntax is what
+ * `Disassembler::disassemble_function` prints, so a listing assembles to
+ * exactly the functions it was printed from:
+ *
+ *     == <script> ==
+ *     0000    1 CLOSURE             1 <fn add>
+ *     0002    | DEFINE_GLOBAL       0 "add"
+ *     ...
+ *
+ *     == add ==
+ *     .arity 2
+ *     0000    2 GET_LOCAL           1
+ *
+ * Each function is a section with a `== label ==` header. The first section
+ * is the script, and `<fn label>` constants refer to the other sections.
+ * Labels are the function's name, optionally followed by `@` and anything
+ * to tell apart functions with the same name; `<script>` has no name.
+ *
+ * An instruction may start with its offset, which is checked, and its
+ * source line, or `|` for the same line as before. Both can be left out
+ * when writing code by hand. Constant operands are literals, with an
+ * optional pool index in front of them. Jumps go to an offset or to a
+ * `label:` defined on a line of its own. A `;` starts a comment. */
+
+use std::{collections::HashMap, fmt::Display};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    debug::quote,
+    memory::{FunctionBuilder, Heap, Trace},
+    object::ObjRef,
+    value::Value,
+};
+
+pub struct Assembler<'a> {
+    source: &'a str,
+    builder: FunctionBuilder<'a>,
+}
+
+/* A function as written in the source, before anything is allocated. */
+struct Section {
+    label: String,
+    arity: usize,
+    upvalue_count: usize,
+    code: Vec<u8>,
+    /* The source line of each byte of code. */
+    lines: Vec<usize>,
+    /* Indexed by position in the pool. Explicit indices can leave gaps
+     * while the section is being read. */
+    constants: Vec<Option<Literal>>,
+    labels: HashMap<String, usize>,
+    jumps: Vec<Jump>,
+    /* The last instruction, which upvalue pairs have to belong to. */
+    last: Option<OpCode>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Literal {
+    Nil,
+    Bool(bool),
+    /* By bit pattern, so that 0 and -0 stay different constants. */
+    Number(u64),
+    String(String),
+    Function(String),
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Nil => write!(f, "nil"),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Number(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            Literal::String(chars) => write!(f, "{}", quote(chars)),
+            Literal::Function(label) => write!(f, "<fn {}>", label),
+        }
+    }
+}
+
+/* A jump operand to fill in once every label in the section is known. */
+struct Jump {
+    /* Offset of the jump instruction. */
+    offset: usize,
+    target: Target,
+    backwards: bool,
+}
+
+enum Target {
+    Offset(usize),
+    Label(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    String(String),
+    Function(String),
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(source: &'a str, heap: &'a mut Heap, roots: &'a dyn Trace) -> Self {
+        Assembler {
+            source,
+            builder: FunctionBuilder::new(heap, roots),
+        }
+    }
+
+    /* Assemble every section and return the script function. */
+    pub fn assemble(&mut self) -> anyhow::Result<ObjRef> {
+        let sections = self.parse()?;
+        let Some(script) = sections.first() else {
+            bail!("Expected a '== <label> ==' section header.");
+        };
+        let mut building = Vec::new();
+        self.build(&script.label, &sections, &mut building)
+    }
+
+    fn parse(&self) -> anyhow::Result<Vec<Section>> {
+        let mut sections: Vec<Section> = Vec::new();
+        for (index, text) in self.source.lines().enumerate() {
+            let source_line = index + 1;
+            let tokens = tokenize(text).map_err(|message| error(source_line, &message))?;
+            if tokens.is_empty() {
+                continue;
+            }
+
+            if tokens[0] == word("==") {
+                let [_, Token::Word(label), end] = tokens.as_slice() else {
+                    return Err(error(source_line, "Expected '== <label> =='"));
+                };
+                if *end != word("==") {
+                    return Err(error(source_line, "Expected '== <label> =='"));
+                }
+                if sections.iter().any(|section| section.label == *label) {
+                    return Err(error(
+                        source_line,
+                        &format!("Duplicate section '{}'", label),
+                    ));
+                }
+                sections.push(Section::new(label.clone()));
+                continue;
+            }
+
+            let Some(section) = sections.last_mut() else {
+                return Err(error(
+                    source_line,
+                    "Expected a '== <label> ==' section header",
+                ));
+            };
+            section
+                .parse_line(&tokens)
+                .map_err(|message| error(source_line, &message))?;
+        }
+
+        for section in &mut sections {
+            section.finish()?;
+        }
+        Ok(sections)
+    }
+
+    /* Allocate a section's function, after the functions in its constants. */
+    fn build(
+        &mut self,
+        label: &str,
+        sections: &[Section],
+        building: &mut Vec<String>,
+    ) -> anyhow::Result<ObjRef> {
+        let section = sections
+            .iter()
+            .find(|section| section.label == label)
+            .with_context(|| format!("No section for function '{}'.", label))?;
+        if building.iter().any(|outer| outer == label) {
+            bail!("Function '{}' contains itself.", label);
+        }
+        building.push(label.to_owned());
+
+        let mut chunk = Chunk::new();
+        chunk.code = section.code.clone();
+        for &line in &section.lines {
+            chunk.lines.push(line);
+        }
+        self.builder.begin(chunk)?;
+
+        for literal in &section.constants {
+            let constant = match literal.as_ref().expect("checked by finish") {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Number(bits) => Value::Double(f64::from_bits(*bits)),
+                Literal::String(chars) => Value::Obj(self.builder.alloc_string(chars.clone())),
+                Literal::Function(label) => Value::Obj(self.build(label, sections, building)?),
+            };
+            self.builder.push_constant(constant);
+        }
+
+        let name = match label.split('@').next().unwrap() {
+            "<script>" => None,
+            name => Some(self.builder.alloc_string(name.to_owned())),
+        };
+
+        building.pop();
+        Ok(self
+            .builder
+            .finish(section.arity, section.upvalue_count, name))
+    }
+}
+
+impl Section {
+    fn new(label: String) -> Self {
+        Section {
+            label,
+            arity: 0,
+            upvalue_count: 0,
+            code: Vec::new(),
+            lines: Vec::new(),
+            constants: Vec::new(),
+            labels: HashMap::new(),
+            jumps: Vec::new(),
+            last: None,
+        }
+    }
+
+    fn parse_line(&mut self, tokens: &[Token]) -> Result<(), String> {
+        let mut tokens = tokens.iter().peekable();
+
+        let first = as_word(tokens.peek().copied())?;
+        if let Some(directive) = first.strip_prefix('.') {
+            tokens.next();
+            let value = parse_number(as_word(tokens.next())?)?;
+            match directive {
+                "arity" => self.arity = value,
+                "upvalues" => self.upvalue_count = value,
+                _ => return Err(format!("Unknown directive '.{}'", directive)),
+            }
+            return expect_end(tokens);
+        }
+        if let Some(label) = first.strip_suffix(':') {
+            tokens.next();
+            if self
+                .labels
+                .insert(label.to_owned(), self.code.len())
+                .is_some()
+            {
+                return Err(format!("Duplicate label '{}'", label));
+            }
+            return expect_end(tokens);
+        }
+
+        // Up to two columns before the mnemonic: the offset and the line.
+        let mut columns = Vec::new();
+        while let Some(Token::Word(column)) = tokens.peek() {
+            if column != "|" && !column.bytes().all(|b| b.is_ascii_digit()) {
+                break;
+            }
+            columns.push(column.as_str());
+            tokens.next();
+        }
+        let (offset, line) = match columns.as_slice() {
+            [] => (None, None),
+            [line] => (None, Some(*line)),
+            [offset, line] => (Some(*offset), Some(*line)),
+            _ => return Err("Expected an instruction".to_owned()),
+        };
+        if let Some(offset) = offset {
+            let offset = parse_number(offset)?;
+            if offset != self.code.len() {
+                return Err(format!(
+                    "Offset {} doesn't match the code so far ({})",
+                    offset,
+                    self.code.len()
+                ));
+            }
+        }
+        let line = match line {
+            Some("|") | None => self.lines.last().copied().unwrap_or(1),
+            Some(line) => parse_number(line)?,
+        };
+
+        let mnemonic = as_word(tokens.next())?;
+        if mnemonic == "local" || mnemonic == "upvalue" {
+            if !matches!(self.last, Some(OpCode::Closure | OpCode::ClosureLong)) {
+                return Err(format!("'{}' must follow CLOSURE", mnemonic));
+            }
+            let index = parse_byte(as_word(tokens.next())?)?;
+            self.emit(u8::from(mnemonic == "local"), line);
+            self.emit(index, line);
+            return expect_end(tokens);
+        }
+        let opcode = OpCode::from_mnemonic(mnemonic)
+            .ok_or_else(|| format!("Unknown instruction '{}'", mnemonic))?;
+        self.last = Some(opcode);
+        let start = self.code.len();
+        self.emit(opcode, line);
+
+        match opcode {
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Print
+            | OpCode::Pop
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit => (),
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => {
+                let operand = parse_byte(as_word(tokens.next())?)?;
+                self.emit(operand, line);
+            }
+            OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::DefineGlobal
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobal
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobal
+            | OpCode::SetGlobalLong
+            | OpCode::GetProperty
+            | OpCode::GetPropertyLong
+            | OpCode::SetProperty
+            | OpCode::SetPropertyLong
+            | OpCode::GetSuper
+            | OpCode::GetSuperLong
+            | OpCode::Class
+            | OpCode::ClassLong
+            | OpCode::Method
+            | OpCode::MethodLong
+            | OpCode::Closure
+            | OpCode::ClosureLong => {
+                let index = self.constant_operand(opcode, &mut tokens)?;
+                self.emit_constant_index(opcode, index, line);
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                // The listing shows the jump's own offset before the arrow.
+                if tokens.peek() != Some(&&word("->")) {
+                    parse_number(as_word(tokens.next())?)?;
+                }
+                if tokens.next() != Some(&word("->")) {
+                    return Err("Expected '->' before the jump target".to_owned());
+                }
+                let target = as_word(tokens.next())?;
+                let target = match target.parse() {
+                    Ok(offset) => Target::Offset(offset),
+                    Err(_) => Target::Label(target.to_owned()),
+                };
+                self.jumps.push(Jump {
+                    offset: start,
+                    target,
+                    backwards: matches!(opcode, OpCode::Loop),
+                });
+                self.emit(0xff, line);
+                self.emit(0xff, line);
+            }
+            OpCode::Invoke | OpCode::InvokeLong | OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+                // (N args)
+                if tokens.next() != Some(&word("(")) {
+                    return Err("Expected '(<count> args)'".to_owned());
+                }
+                let arg_count = parse_byte(as_word(tokens.next())?)?;
+                if tokens.next() != Some(&word("args")) || tokens.next() != Some(&word(")")) {
+                    return Err("Expected '(<count> args)'".to_owned());
+                }
+                let index = self.constant_operand(opcode, &mut tokens)?;
+                self.emit_constant_index(opcode, index, line);
+                self.emit(arg_count, line);
+            }
+        }
+        expect_end(tokens)
+    }
+
+    /* A literal, optionally after its index in the pool. Without an index,
+     * the literal reuses an equal constant or goes at the end. Either way
+     * the index has to fit in the instruction's operand. */
+    fn constant_operand<'t>(
+        &mut self,
+        opcode: OpCode,
+        tokens: &mut impl Iterator<Item = &'t Token>,
+    ) -> Result<usize, String> {
+        let mut token = tokens.next();
+        let mut index = None;
+        if let Some(Token::Word(word)) = token {
+            if word.bytes().all(|b| b.is_ascii_digit()) {
+                if let Some(literal) = tokens.next() {
+                    index = Some(parse_number(word)?);
+                    token = Some(literal);
+                }
+            }
+        }
+        let literal = parse_literal(token)?;
+
+        let index = match index {
+            Some(index) => index,
+            None => self
+                .constants
+                .iter()
+                .position(|constant| constant.as_ref() == Some(&literal))
+                .unwrap_or(self.constants.len()),
+        };
+        let limit = if opcode.is_long() { 1 << 24 } else { 1 << 8 };
+        if index >= limit {
+            return Err(format!(
+                "Constant {} is too large for {}",
+                index,
+                opcode.mnemonic()
+            ));
+        }
+        if index >= self.constants.len() {
+            self.constants.resize(index + 1, None);
+        }
+        match &self.constants[index] {
+            Some(existing) if *existing != literal => {
+                Err(format!("Constant {} is already {}", index, existing))
+            }
+            _ => {
+                self.constants[index] = Some(literal);
+                Ok(index)
+            }
+        }
+    }
+
+    /* A constant index takes one byte, or three in the long forms. */
+    fn emit_constant_index(&mut self, opcode: OpCode, index: usize, line: usize) {
+        if opcode.is_long() {
+            let [_, high, middle, low] = (index as u32).to_be_bytes();
+            self.emit(high, line);
+            self.emit(middle, line);
+            self.emit(low, line);
+        } else {
+            self.emit(index as u8, line);
+        }
+    }
+
+    fn emit(&mut self, byte: impl Into<u8>, line: usize) {
+        self.code.push(byte.into());
+        self.lines.push(line);
+    }
+
+    /* Fill in jump offsets and check the constant pool has no gaps. */
+    fn finish(&mut self) -> anyhow::Result<()> {
+        for jump in &self.jumps {
+            let target = match &jump.target {
+                Target::Offset(offset) => *offset,
+                Target::Label(label) => *self.labels.get(label).ok_or_else(|| {
+                    anyhow!("Undefined label '{}' in section '{}'.", label, self.label)
+                })?,
+            };
+            let next = jump.offset + 3;
+            let distance = if jump.backwards {
+                next.checked_sub(target)
+            } else {
+                target.checked_sub(next)
+            };
+            let Some(distance) = distance.and_then(|distance| u16::try_from(distance).ok()) else {
+                bail!(
+                    "Can't jump from {} to {} in section '{}'.",
+                    jump.offset,
+                    target,
+                    self.label
+                );
+            };
+            let [high, low] = distance.to_be_bytes();
+            self.code[jump.offset + 1] = high;
+            self.code[jump.offset + 2] = low;
+        }
+
+        if let Some(index) = self.constants.iter().position(Option::is_none) {
+            bail!(
+                "Constant {} in section '{}' is never given a value.",
+                index,
+                self.label
+            );
+        }
+        Ok(())
+    }
+}
+
+fn parse_literal(token: Option<&Token>) -> Result<Literal, String> {
+    Ok(match token {
+        Some(Token::String(chars)) => Literal::String(chars.clone()),
+        Some(Token::Function(label)) => Literal::Function(label.clone()),
+        Some(Token::Word(word)) => match word.as_str() {
+            "nil" => Literal::Nil,
+            "true" => Literal::Bool(true),
+            "false" => Literal::Bool(false),
+            number => Literal::Number(
+                number
+                    .parse::<f64>()
+                    .map_err(|_| format!("Expected a constant, got '{}'", number))?
+                    .to_bits(),
+            ),
+        },
+        None => return Err("Expected a constant".to_owned()),
+    })
+}
+
+fn parse_number(word: &str) -> Result<usize, String> {
+    word.parse()
+        .map_err(|_| format!("Expected a number, got '{}'", word))
+}
+
+fn parse_byte(word: &str) -> Result<u8, String> {
+    word.parse()
+        .map_err(|_| format!("Expected a number from 0 to 255, got '{}'", word))
+}
+
+fn as_word(token: Option<&Token>) -> Result<&str, String> {
+    match token {
+        Some(Token::Word(word)) => Ok(word),
+        Some(token) => Err(format!("Unexpected {:?}", token)),
+        None => Err("Unexpected end of line".to_owned()),
+    }
+}
+
+fn expect_end<'t>(mut tokens: impl Iterator<Item = &'t Token>) -> Result<(), String> {
+    match tokens.next() {
+        None => Ok(()),
+        Some(token) => Err(format!("Unexpected {:?}", token)),
+    }
+}
+
+fn word(word: &str) -> Token {
+    Token::Word(word.to_owned())
+}
+
+/* Split a line into words, quoted strings and `<fn label>` references.
+ * Parentheses are words of their own. */
+fn tokenize(line: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            ';' => break,
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' | ')' => {
+                chars.next();
+                tokens.push(Token::Word(c.to_string()));
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => string.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('r') => '\r',
+                            Some('t') => '\t',
+                            Some(c @ ('"' | '\\')) => c,
+                            _ => return Err("Invalid escape in string".to_owned()),
+                        }),
+                        Some(c) => string.push(c),
+                        None => return Err("Unterminated string".to_owned()),
+                    }
+                }
+                tokens.push(Token::String(string));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || matches!(c, '(' | ')' | '"' | ';') {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                if word == "<fn" {
+                    let label: String = chars.by_ref().take_while(|&c| c != '>').collect();
+                    tokens.push(Token::Function(label.trim().to_owned()));
+                } else {
+                    tokens.push(Token::Word(word));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+fn error(source_line: usize, message: &str) -> anyhow::Error {
+    anyhow!("[line {}] Error: {}.", source_line, message)
+}
@@ -1,28 +1,204 @@
 use derive_try_from_primitive::TryFromPrimitive;
 
-use crate::value::Value;
-#[derive(TryFromPrimitive, Debug, Clone, Copy)]
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{object::ObjRef, value::Value};
+#[derive(TryFromPrimitive, Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
 pub enum OpCode {
     Constant,
+    ConstantLong,
+    Nil,
+    True,
+    False,
+    Equal,
+    Greater,
+    Less,
     Add,
     Subtract,
     Multiply,
     Divide,
+    Not,
     Negate,
+    Print,
+    Pop,
+    GetLocal,
+    SetLocal,
+    DefineGlobal,
+    DefineGlobalLong,
+    GetGlobal,
+    GetGlobalLong,
+    SetGlobal,
+    SetGlobalLong,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    GetPropertyLong,
+    SetProperty,
+    SetPropertyLong,
+    GetSuper,
+    GetSuperLong,
+    Jump,
+    JumpIfFalse,
+    Loop,
+    Call,
+    Invoke,
+    InvokeLong,
+    SuperInvoke,
+    SuperInvokeLong,
+    Closure,
+    ClosureLong,
+    CloseUpvalue,
     Return,
+    Class,
+    ClassLong,
+    Inherit,
+    Method,
+    MethodLong,
 }
 
-impl Into<u8> for OpCode {
-    fn into(self) -> u8 {
-        self as u8
+impl OpCode {
+    /* The name the disassembler prints and the assembler reads. */
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            OpCode::Constant => "CONSTANT",
+            OpCode::ConstantLong => "CONSTANT_LONG",
+            OpCode::Nil => "NIL",
+            OpCode::True => "TRUE",
+            OpCode::False => "FALSE",
+            OpCode::Equal => "EQUAL",
+            OpCode::Greater => "GREATER",
+            OpCode::Less => "LESS",
+            OpCode::Add => "ADD",
+            OpCode::Subtract => "SUBTRACT",
+            OpCode::Multiply => "MULTIPLY",
+            OpCode::Divide => "DIVIDE",
+            OpCode::Not => "NOT",
+            OpCode::Negate => "NEGATE",
+            OpCode::Print => "PRINT",
+            OpCode::Pop => "POP",
+            OpCode::GetLocal => "GET_LOCAL",
+            OpCode::SetLocal => "SET_LOCAL",
+            OpCode::DefineGlobal => "DEFINE_GLOBAL",
+            OpCode::DefineGlobalLong => "DEFINE_GLOBAL_LONG",
+            OpCode::GetGlobal => "GET_GLOBAL",
+            OpCode::GetGlobalLong => "GET_GLOBAL_LONG",
+            OpCode::SetGlobal => "SET_GLOBAL",
+            OpCode::SetGlobalLong => "SET_GLOBAL_LONG",
+            OpCode::GetUpvalue => "GET_UPVALUE",
+            OpCode::SetUpvalue => "SET_UPVALUE",
+            OpCode::GetProperty => "GET_PROPERTY",
+            OpCode::GetPropertyLong => "GET_PROPERTY_LONG",
+            OpCode::SetProperty => "SET_PROPERTY",
+            OpCode::SetPropertyLong => "SET_PROPERTY_LONG",
+            OpCode::GetSuper => "GET_SUPER",
+            OpCode::GetSuperLong => "GET_SUPER_LONG",
+            OpCode::Jump => "JUMP",
+            OpCode::JumpIfFalse => "JUMP_IF_FALSE",
+            OpCode::Loop => "LOOP",
+            OpCode::Call => "CALL",
+            OpCode::Invoke => "INVOKE",
+            OpCode::InvokeLong => "INVOKE_LONG",
+            OpCode::SuperInvoke => "SUPER_INVOKE",
+            OpCode::SuperInvokeLong => "SUPER_INVOKE_LONG",
+            OpCode::Closure => "CLOSURE",
+            OpCode::ClosureLong => "CLOSURE_LONG",
+            OpCode::CloseUpvalue => "CLOSE_UPVALUE",
+            OpCode::Return => "RETURN",
+            OpCode::Class => "CLASS",
+            OpCode::ClassLong => "CLASS_LONG",
+            OpCode::Inherit => "INHERIT",
+            OpCode::Method => "METHOD",
+            OpCode::MethodLong => "METHOD_LONG",
+        }
+    }
+
+    /* The form of an instruction with a constant operand that takes a
+     * three-byte index, for constants past the first 256. */
+    pub fn long(self) -> Option<OpCode> {
+        Some(match self {
+            OpCode::Constant => OpCode::ConstantLong,
+            OpCode::DefineGlobal => OpCode::DefineGlobalLong,
+            OpCode::GetGlobal => OpCode::GetGlobalLong,
+            OpCode::SetGlobal => OpCode::SetGlobalLong,
+            OpCode::GetProperty => OpCode::GetPropertyLong,
+            OpCode::SetProperty => OpCode::SetPropertyLong,
+            OpCode::GetSuper => OpCode::GetSuperLong,
+            OpCode::Invoke => OpCode::InvokeLong,
+            OpCode::SuperInvoke => OpCode::SuperInvokeLong,
+            OpCode::Closure => OpCode::ClosureLong,
+            OpCode::Class => OpCode::ClassLong,
+            OpCode::Method => OpCode::MethodLong,
+            _ => return None,
+        })
+    }
+
+    pub fn is_long(self) -> bool {
+        matches!(
+            self,
+            OpCode::ConstantLong
+                | OpCode::DefineGlobalLong
+                | OpCode::GetGlobalLong
+                | OpCode::SetGlobalLong
+                | OpCode::GetPropertyLong
+                | OpCode::SetPropertyLong
+                | OpCode::GetSuperLong
+                | OpCode::InvokeLong
+                | OpCode::SuperInvokeLong
+                | OpCode::ClosureLong
+                | OpCode::ClassLong
+                | OpCode::MethodLong
+        )
+    }
+
+    /* The opcode with this mnemonic, if there is one. */
+    pub fn from_mnemonic(mnemonic: &str) -> Option<OpCode> {
+        (0..=u8::MAX)
+            .map_while(|byte| OpCode::try_from(byte).ok())
+            .find(|opcode| opcode.mnemonic() == mnemonic)
+    }
+}
+
+impl From<OpCode> for u8 {
+    fn from(val: OpCode) -> Self {
+        val as u8
     }
 }
 
 pub struct Chunk {
     pub code: Vec<u8>,
     pub constants: Vec<Value>,
-    pub lines: Vec<usize>,
+    pub lines: LineTable,
+    /* Where each constant already in the pool is, so it is only added once. */
+    constant_indices: HashMap<ConstantKey, usize>,
+}
+
+/* Constants are the same if they are the same object or the same number
+ * down to the bit pattern. Comparing by value would merge 0 and -0, which
+ * print differently, and would never find a NaN. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Bool(bool),
+    Nil,
+    Double(u64),
+    Obj(ObjRef),
+}
+
+impl From<Value> for ConstantKey {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Bool(b) => ConstantKey::Bool(b),
+            Value::Nil => ConstantKey::Nil,
+            Value::Double(n) => ConstantKey::Double(n.to_bits()),
+            Value::Obj(obj) => ConstantKey::Obj(obj),
+        }
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chunk {
@@ -30,7 +206,8 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             constants: Vec::new(),
-            lines: Vec::new(),
+            lines: LineTable::default(),
+            constant_indices: HashMap::new(),
         }
     }
 
@@ -42,8 +219,282 @@ impl Chunk {
         self.lines.push(line);
     }
 
+    /* The source line of the byte at `offset`. */
+    pub fn line(&self, offset: usize) -> usize {
+        self.lines.line(offset)
+    }
+
+    /* The instruction starting at `offset`. */
+    pub fn decode(&self, offset: usize) -> Result<Instruction, DecodeError> {
+        let error = |kind| DecodeError { offset, kind };
+        let byte = |index: usize| {
+            self.code
+                .get(offset + index)
+                .copied()
+                .ok_or(error(DecodeErrorKind::Truncated))
+        };
+
+        let opcode = byte(0)?;
+        let opcode =
+            OpCode::try_from(opcode).map_err(|_| error(DecodeErrorKind::UnknownOpcode(opcode)))?;
+        // A constant index right after the opcode, and the bytes it takes.
+        let constant = || -> Result<(usize, usize), DecodeError> {
+            if opcode.is_long() {
+                let index = u32::from_be_bytes([0, byte(1)?, byte(2)?, byte(3)?]);
+                Ok((index as usize, 3))
+            } else {
+                Ok((byte(1)? as usize, 1))
+            }
+        };
+        let (operand, len) = match opcode {
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Print
+            | OpCode::Pop
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit => (Operand::None, 1),
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => (Operand::Byte(byte(1)?), 2),
+            OpCode::Constant
+            | OpCode::ConstantLong
+            | OpCode::DefineGlobal
+            | OpCode::DefineGlobalLong
+            | OpCode::GetGlobal
+            | OpCode::GetGlobalLong
+            | OpCode::SetGlobal
+            | OpCode::SetGlobalLong
+            | OpCode::GetProperty
+            | OpCode::GetPropertyLong
+            | OpCode::SetProperty
+            | OpCode::SetPropertyLong
+            | OpCode::GetSuper
+            | OpCode::GetSuperLong
+            | OpCode::Class
+            | OpCode::ClassLong
+            | OpCode::Method
+            | OpCode::MethodLong => {
+                let (index, width) = constant()?;
+                (Operand::Constant(index), 1 + width)
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = u16::from_be_bytes([byte(1)?, byte(2)?]) as isize;
+                let sign = if opcode == OpCode::Loop { -1 } else { 1 };
+                let target = (offset + 3).wrapping_add_signed(sign * jump);
+                (Operand::Jump(target), 3)
+            }
+            OpCode::Invoke | OpCode::InvokeLong | OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+                let (constant, width) = constant()?;
+                let arg_count = byte(1 + width)?;
+                (
+                    Operand::Invoke {
+                        constant,
+                        arg_count,
+                    },
+                    2 + width,
+                )
+            }
+            // The function says how many upvalue pairs follow.
+            OpCode::Closure | OpCode::ClosureLong => {
+                let (constant, width) = constant()?;
+                let upvalue_count = match self.constants.get(constant) {
+                    Some(Value::Obj(obj)) => obj.as_function().map(|f| f.upvalue_count),
+                    _ => None,
+                }
+                .ok_or(error(DecodeErrorKind::NotAFunction))?;
+
+                let mut upvalues = Vec::new();
+                for i in 0..upvalue_count {
+                    let is_local = match byte(1 + width + 2 * i)? {
+                        0 => false,
+                        1 => true,
+                        kind => return Err(error(DecodeErrorKind::InvalidCapture(kind))),
+                    };
+                    let index = byte(2 + width + 2 * i)?;
+                    upvalues.push(Capture { is_local, index });
+                }
+                (
+                    Operand::Closure { constant, upvalues },
+                    1 + width + 2 * upvalue_count,
+                )
+            }
+        };
+
+        Ok(Instruction {
+            offset,
+            opcode,
+            operand,
+            len,
+        })
+    }
+
+    /* Every instruction in order. Stops after the first one that can't be
+     * decoded, since nothing after it can be trusted. */
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            chunk: self,
+            offset: 0,
+        }
+    }
+
+    /* Add a constant to the pool, or find the identical one already there.
+     * Strings are interned, so equal strings share an entry. */
     pub fn add_constant(&mut self, value: Value) -> usize {
-        self.constants.push(value);
-        self.constants.len() - 1
+        *self
+            .constant_indices
+            .entry(value.into())
+            .or_insert_with(|| {
+                self.constants.push(value);
+                self.constants.len() - 1
+            })
+    }
+}
+
+/* One instruction, decoded from a chunk's code. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub offset: usize,
+    pub opcode: OpCode,
+    pub operand: Operand,
+    /* Bytes of code it takes up, the opcode included. */
+    pub len: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    None,
+    /* A stack slot, upvalue index or argument count. */
+    Byte(u8),
+    /* An index into the constant pool. */
+    Constant(usize),
+    /* The offset the jump goes to. */
+    Jump(usize),
+    Invoke {
+        constant: usize,
+        arg_count: u8,
+    },
+    /* The function's constant, then where each of its upvalues comes from. */
+    Closure {
+        constant: usize,
+        upvalues: Vec<Capture>,
+    },
+}
+
+/* Whether a closure captures a local of the enclosing function, or one of
+ * its upvalues, and which. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capture {
+    pub is_local: bool,
+    pub index: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError {
+    /* Where the instruction that couldn't be decoded starts. */
+    pub offset: usize,
+    pub kind: DecodeErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    UnknownOpcode(u8),
+    Truncated,
+    /* A closure whose constant isn't a function, so there is no knowing
+     * how many upvalue pairs follow it. */
+    NotAFunction,
+    InvalidCapture(u8),
+}
+
+impl Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeErrorKind::UnknownOpcode(byte) => write!(f, "unknown opcode {}", byte),
+            DecodeErrorKind::Truncated => write!(f, "truncated operand"),
+            DecodeErrorKind::NotAFunction => write!(f, "closure operand is not a function"),
+            DecodeErrorKind::InvalidCapture(kind) => write!(f, "invalid upvalue kind {}", kind),
+        }
+    }
+}
+
+pub struct Instructions<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.chunk.code.len() {
+            return None;
+        }
+        let instruction = self.chunk.decode(self.offset);
+        self.offset = match &instruction {
+            Ok(instruction) => self.offset + instruction.len,
+            Err(_) => self.chunk.code.len(),
+        };
+        Some(instruction)
+    }
+}
+
+/* Maps bytecode offsets to source lines. Consecutive bytes almost always
+ * share a line, so only the offset where each new line starts is stored. */
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    runs: Vec<LineRun>,
+    len: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct LineRun {
+    /* Offset of the first byte on this line. */
+    start: usize,
+    line: usize,
+}
+
+impl LineTable {
+    /* Record the line of the next byte of code. */
+    pub fn push(&mut self, line: usize) {
+        if self.runs.last().is_none_or(|run| run.line != line) {
+            self.runs.push(LineRun {
+                start: self.len,
+                line,
+            });
+        }
+        self.len += 1;
+    }
+
+    pub fn line(&self, offset: usize) -> usize {
+        assert!(
+            offset < self.len,
+            "offset {} is past the end of the code",
+            offset
+        );
+        // The last run starting at or before the offset.
+        let run = self.runs.partition_point(|run| run.start <= offset) - 1;
+        self.runs[run].line
+    }
+
+    /* Each line with the offset of the first byte on it, in code order. */
+    pub fn runs(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.runs.iter().map(|run| (run.start, run.line))
+    }
+
+    /* Bytes of heap storage the table holds on to. */
+    pub fn capacity_bytes(&self) -> usize {
+        self.runs.capacity() * std::mem::size_of::<LineRun>()
     }
 }
@@ -1,11 +1,12 @@
 use core::str;
 
-use anyhow::bail;
-
 use crate::{
     chunk::{Chunk, OpCode},
+    memory::{Heap, Trace, MAX_FUNCTION_DEPTH},
+    object::{ObjFunction, ObjKind, ObjRef},
     scanner::{Scanner, Token, TokenType},
     value::Value,
+    vm::InterpretError,
 };
 
 pub struct Compiler<'a> {
@@ -14,52 +15,199 @@ pub struct Compiler<'a> {
     scanner: Scanner<'a>,
     had_error: bool,
     panic_mode: bool,
-    compiling_chunk: Option<Chunk>,
+    heap: &'a mut Heap,
+    /* What the vm keeps alive while this compiler allocates. */
+    roots: &'a dyn Trace,
+    /* The functions being compiled, innermost last. */
+    functions: Vec<FunctionState<'a>>,
+    /* The class declarations being compiled, innermost last. */
+    classes: Vec<ClassState>,
+}
+
+/* What the compiler tracks for a class body. */
+struct ClassState {
+    has_superclass: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FunctionType {
+    Function,
+    Initializer,
+    Method,
+    Script,
+}
+
+/* Everything the compiler tracks for one function body. */
+struct FunctionState<'a> {
+    function_type: FunctionType,
+    name: Option<ObjRef>,
+    arity: usize,
+    chunk: Chunk,
+    /* Locals in scope, in the order their values sit in the frame's slots. */
+    locals: Vec<Local<'a>>,
+    /* Variables captured from enclosing functions, in operand order. */
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+}
+
+impl<'a> FunctionState<'a> {
+    fn new(function_type: FunctionType, name: Option<ObjRef>) -> Self {
+        FunctionState {
+            function_type,
+            name,
+            arity: 0,
+            chunk: Chunk::new(),
+            // Slot zero holds the function being called, which in methods is
+            // the receiver, reachable as `this`.
+            locals: vec![Local {
+                name: match function_type {
+                    FunctionType::Function | FunctionType::Script => b"",
+                    FunctionType::Initializer | FunctionType::Method => b"this",
+                },
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+        }
+    }
+}
+
+impl Trace for FunctionState<'_> {
+    fn trace(&self, heap: &mut Heap) {
+        self.chunk.trace(heap);
+        if let Some(name) = self.name {
+            heap.mark_object(name);
+        }
+    }
+}
+
+struct Local<'a> {
+    name: &'a [u8],
+    /* None while the variable's initializer is being compiled. */
+    depth: Option<usize>,
+    /* Whether a closure captures this local, so it must be moved off the
+     * stack when it goes out of scope. */
+    is_captured: bool,
+}
+
+/* Where a closure finds a captured variable when it is created: a local
+ * slot of the enclosing function, or one of that function's upvalues. */
+#[derive(Clone, Copy)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/* Local slots are addressed by a one-byte operand. */
+const MAX_LOCALS: usize = u8::MAX as usize + 1;
+/* The long forms of instructions address constants with a three-byte
+ * operand. */
+const MAX_LONG_CONSTANTS: usize = 1 << 24;
+/* Upvalues are addressed by a one-byte operand. */
+const MAX_UPVALUES: usize = u8::MAX as usize + 1;
+/* The argument count of a call is a one-byte operand. */
+const MAX_ARGS: usize = u8::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment, // =
+    Or,         // or
+    And,        // and
+    Equality,   // == !=
+    Comparison, // < > <= >=
+    Term,       // + -
+    Factor,     // * /
+    Unary,      // ! -
+    Call,       // . ()
+    Primary,
+}
+
+impl Precedence {
+    /* The next-higher precedence level, used to make binary operators left-associative. */
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+}
+
+/* Prefix and infix rules are told whether an `=` after them may be an
+ * assignment, which is only the case at assignment precedence or lower. */
+type ParseFn<'a> = fn(&mut Compiler<'a>, bool) -> anyhow::Result<()>;
+
+struct ParseRule<'a> {
+    prefix: Option<ParseFn<'a>>,
+    infix: Option<ParseFn<'a>>,
+    precedence: Precedence,
+}
+
+impl<'a> ParseRule<'a> {
+    fn new(
+        prefix: Option<ParseFn<'a>>,
+        infix: Option<ParseFn<'a>>,
+        precedence: Precedence,
+    ) -> Self {
+        ParseRule {
+            prefix,
+            infix,
+            precedence,
+        }
+    }
 }
 
 impl<'a> Compiler<'a> {
-    pub fn new(source: &'a [u8]) -> Self {
+    pub fn new(source: &'a [u8], heap: &'a mut Heap, roots: &'a dyn Trace) -> Self {
         Compiler {
             current: None,
             previous: None,
             scanner: Scanner::new(source),
             had_error: false,
             panic_mode: false,
-            compiling_chunk: None,
+            heap,
+            roots,
+            functions: Vec::new(),
+            classes: Vec::new(),
         }
     }
 
-    pub fn compile(&mut self) -> anyhow::Result<Chunk> {
-        self.compiling_chunk = Some(Chunk::new());
+    /* Compile a whole program into the implicit function that runs it. */
+    pub fn compile(&mut self) -> anyhow::Result<ObjRef> {
+        self.functions
+            .push(FunctionState::new(FunctionType::Script, None));
         self.advance()?;
-
-        // parser.expression();
-        // let mut line = 0;
-        // loop {
-        //     let token = scanner.scan_token();
-
-        //     if token.line() != line {
-        //         print!("{:4} ", line);
-        //     } else {
-        //         print!("   | ");
-        //     }
-
-        //     println!("{:?} {:?}", token.ty(), token.ident());
-
-        //     if token.ty() == TokenType::Eof {
-        //         break;
-        //     }
-        // }
+        while !self.matches(TokenType::Eof)? {
+            self.declaration()?;
+        }
+        let (function, _) = self.end_compiler();
 
         if self.had_error {
-            bail!("Parser had error");
+            return Err(InterpretError::Compile.into());
         }
 
-        Ok(self.compiling_chunk.take().unwrap())
+        Ok(function)
+    }
+
+    fn function_state(&self) -> &FunctionState<'a> {
+        self.functions.last().expect("compiling a function")
+    }
+
+    fn function_state_mut(&mut self) -> &mut FunctionState<'a> {
+        self.functions.last_mut().expect("compiling a function")
     }
 
     fn current_chunk(&mut self) -> &mut Chunk {
-        self.compiling_chunk.as_mut().unwrap()
+        &mut self.function_state_mut().chunk
     }
 
     fn emit_byte(&mut self, byte: impl Into<u8>) {
@@ -72,11 +220,11 @@ impl<'a> Compiler<'a> {
         self.emit_byte(byte2);
     }
 
-    pub fn current(&self) -> &Token {
+    pub fn current(&self) -> &Token<'_> {
         self.current.as_ref().unwrap()
     }
 
-    pub fn previous(&self) -> &Token {
+    pub fn previous(&self) -> &Token<'_> {
         self.previous.as_ref().unwrap()
     }
 
@@ -117,7 +265,7 @@ impl<'a> Compiler<'a> {
             eprint!(" at end");
         } else if token.ty() == TokenType::Error {
         } else {
-            eprint!(" at {:?}", token.ident());
+            eprint!(" at '{}'", String::from_utf8_lossy(token.ident()));
         }
 
         eprintln!(": {}", message);
@@ -135,42 +283,851 @@ impl<'a> Compiler<'a> {
         Ok(())
     }
 
+    fn check(&self, ty: TokenType) -> bool {
+        self.current().ty() == ty
+    }
+
+    fn matches(&mut self, ty: TokenType) -> anyhow::Result<bool> {
+        if !self.check(ty) {
+            return Ok(false);
+        }
+        self.advance()?;
+        Ok(true)
+    }
+
     fn expression(&mut self) -> anyhow::Result<()> {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    fn declaration(&mut self) -> anyhow::Result<()> {
+        if self.matches(TokenType::Class)? {
+            self.class_declaration()?;
+        } else if self.matches(TokenType::Fun)? {
+            self.fun_declaration()?;
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?;
+        } else {
+            self.statement()?;
+        }
+
+        if self.panic_mode {
+            self.synchronize()?;
+        }
+
+        Ok(())
+    }
+
+    fn class_declaration(&mut self) -> anyhow::Result<()> {
+        self.consume(TokenType::Identifier, "Expect class name.")?;
+        let class_name = self.previous.clone().unwrap().ident();
+        let name_constant = self.identifier_constant(class_name);
+        self.declare_variable();
+
+        self.emit_constant_operand(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+
+        if self.matches(TokenType::Less)? {
+            self.consume(TokenType::Identifier, "Expect superclass name.")?;
+            self.variable(false)?;
+            if self.previous().ident() == class_name {
+                self.error("A class can't inherit from itself.");
+            }
+
+            // Methods reach the superclass through a local named `super`,
+            // scoped to this class body.
+            self.begin_scope();
+            self.add_local(b"super");
+            self.define_variable(0);
+
+            self.named_variable(class_name, false)?;
+            self.emit_byte(OpCode::Inherit);
+            self.classes.last_mut().unwrap().has_superclass = true;
+        }
+
+        // Load the class so methods can be bound to it.
+        self.named_variable(class_name, false)?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before class body.")?;
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.method()?;
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after class body.")?;
+        self.emit_byte(OpCode::Pop);
+
+        if self.classes.pop().unwrap().has_superclass {
+            self.end_scope();
+        }
+
+        Ok(())
+    }
+
+    fn method(&mut self) -> anyhow::Result<()> {
+        self.consume(TokenType::Identifier, "Expect method name.")?;
+        let name = self.previous.clone().unwrap().ident();
+        let constant = self.identifier_constant(name);
+
+        let function_type = if name == b"init" {
+            FunctionType::Initializer
+        } else {
+            FunctionType::Method
+        };
+        self.function(function_type)?;
+        self.emit_constant_operand(OpCode::Method, constant);
+
+        Ok(())
+    }
+
+    fn fun_declaration(&mut self) -> anyhow::Result<()> {
+        let global = self.parse_variable("Expect function name.")?;
+        // A function may refer to itself, so it is usable before its body
+        // is compiled.
+        self.mark_initialized();
+        self.function(FunctionType::Function)?;
+        self.define_variable(global);
+
+        Ok(())
+    }
+
+    /* Compile a function's parameters and body and emit it as a constant. */
+    fn function(&mut self, function_type: FunctionType) -> anyhow::Result<()> {
+        let name = self.previous.clone().unwrap();
+        let name = self.alloc_string(String::from_utf8_lossy(name.ident()).into_owned());
+        // Anything deeper couldn't be loaded back from a .loxc file.
+        if self.functions.len() == MAX_FUNCTION_DEPTH {
+            self.error("Too many nested functions.");
+        }
+        self.functions
+            .push(FunctionState::new(function_type, Some(name)));
+        self.begin_scope();
+
+        self.consume(TokenType::LeftParen, "Expect '(' after function name.")?;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.function_state_mut().arity += 1;
+                if self.function_state().arity > MAX_ARGS {
+                    self.error_at_current("Can't have more than 255 parameters.");
+                }
+                let constant = self.parse_variable("Expect parameter name.")?;
+                self.define_variable(constant);
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after parameters.")?;
+        self.consume(TokenType::LeftBrace, "Expect '{' before function body.")?;
+        self.block()?;
+
+        // No end_scope: the frame's slots go away when the function returns.
+        let (function, upvalues) = self.end_compiler();
+        let constant = self.current_chunk().add_constant(Value::Obj(function));
+        self.emit_constant_operand(OpCode::Closure, constant);
+        for upvalue in upvalues {
+            self.emit_bytes(u8::from(upvalue.is_local), upvalue.index);
+        }
+
+        Ok(())
+    }
+
+    fn var_declaration(&mut self) -> anyhow::Result<()> {
+        let global = self.parse_variable("Expect variable name.")?;
+
+        if self.matches(TokenType::Equal)? {
+            self.expression()?;
+        } else {
+            self.emit_byte(OpCode::Nil);
+        }
+        self.consume(
+            TokenType::Semicolon,
+            "Expect ';' after variable declaration.",
+        )?;
+
+        self.define_variable(global);
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> anyhow::Result<()> {
+        if self.matches(TokenType::Print)? {
+            self.print_statement()
+        } else if self.matches(TokenType::Return)? {
+            self.return_statement()
+        } else if self.matches(TokenType::For)? {
+            self.for_statement()
+        } else if self.matches(TokenType::If)? {
+            self.if_statement()
+        } else if self.matches(TokenType::While)? {
+            self.while_statement()
+        } else if self.matches(TokenType::LeftBrace)? {
+            self.begin_scope();
+            self.block()?;
+            self.end_scope();
+            Ok(())
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    fn block(&mut self) -> anyhow::Result<()> {
+        while !self.check(TokenType::RightBrace) && !self.check(TokenType::Eof) {
+            self.declaration()?;
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")
+    }
+
+    fn begin_scope(&mut self) {
+        self.function_state_mut().scope_depth += 1;
+    }
+
+    /* Pop the locals declared in the scope being left. */
+    fn end_scope(&mut self) {
+        self.function_state_mut().scope_depth -= 1;
+
+        loop {
+            let state = self.function_state();
+            let Some(local) = state.locals.last() else {
+                break;
+            };
+            if local.depth.is_some_and(|depth| depth <= state.scope_depth) {
+                break;
+            }
+            if local.is_captured {
+                self.emit_byte(OpCode::CloseUpvalue);
+            } else {
+                self.emit_byte(OpCode::Pop);
+            }
+            self.function_state_mut().locals.pop();
+        }
+    }
+
+    fn if_statement(&mut self) -> anyhow::Result<()> {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'if'.")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement()?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_byte(OpCode::Pop);
+
+        if self.matches(TokenType::Else)? {
+            self.statement()?;
+        }
+        self.patch_jump(else_jump);
+
+        Ok(())
+    }
+
+    fn while_statement(&mut self) -> anyhow::Result<()> {
+        let loop_start = self.current_chunk().code.len();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'while'.")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "Expect ')' after condition.")?;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
+        self.emit_byte(OpCode::Pop);
+        self.statement()?;
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_byte(OpCode::Pop);
+
+        Ok(())
+    }
+
+    fn for_statement(&mut self) -> anyhow::Result<()> {
+        self.begin_scope();
+        self.consume(TokenType::LeftParen, "Expect '(' after 'for'.")?;
+        if self.matches(TokenType::Semicolon)? {
+            // No initializer.
+        } else if self.matches(TokenType::Var)? {
+            self.var_declaration()?;
+        } else {
+            self.expression_statement()?;
+        }
+
+        let mut loop_start = self.current_chunk().code.len();
+        let mut exit_jump = None;
+        if !self.matches(TokenType::Semicolon)? {
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after loop condition.")?;
+
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse));
+            self.emit_byte(OpCode::Pop);
+        }
+
+        // The increment is compiled before the body but runs after it, so
+        // jump over it now and loop back to it at the end of the body.
+        if !self.matches(TokenType::RightParen)? {
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.current_chunk().code.len();
+            self.expression()?;
+            self.emit_byte(OpCode::Pop);
+            self.consume(TokenType::RightParen, "Expect ')' after for clauses.")?;
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement()?;
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_byte(OpCode::Pop);
+        }
+        self.end_scope();
+
+        Ok(())
+    }
+
+    fn return_statement(&mut self) -> anyhow::Result<()> {
+        if self.function_state().function_type == FunctionType::Script {
+            self.error("Can't return from top-level code.");
+        }
+
+        if self.matches(TokenType::Semicolon)? {
+            self.emit_return();
+        } else {
+            if self.function_state().function_type == FunctionType::Initializer {
+                self.error("Can't return a value from an initializer.");
+            }
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "Expect ';' after return value.")?;
+            self.emit_byte(OpCode::Return);
+        }
+
+        Ok(())
+    }
+
+    fn print_statement(&mut self) -> anyhow::Result<()> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after value.")?;
+        self.emit_byte(OpCode::Print);
+
         Ok(())
     }
 
-    fn end_compiler(&mut self) {
+    fn expression_statement(&mut self) -> anyhow::Result<()> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after expression.")?;
+        self.emit_byte(OpCode::Pop);
+
+        Ok(())
+    }
+
+    /* Skip tokens until a likely statement boundary so one mistake doesn't
+     * cascade into a flood of errors. */
+    fn synchronize(&mut self) -> anyhow::Result<()> {
+        self.panic_mode = false;
+
+        while !self.check(TokenType::Eof) {
+            if self.previous().ty() == TokenType::Semicolon {
+                return Ok(());
+            }
+            match self.current().ty() {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return Ok(()),
+                _ => self.advance()?,
+            }
+        }
+
+        Ok(())
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> anyhow::Result<()> {
+        self.advance()?;
+        let Some(prefix_rule) = Self::get_rule(self.previous().ty()).prefix else {
+            self.error("Expect expression.");
+            return Ok(());
+        };
+        let can_assign = precedence <= Precedence::Assignment;
+        prefix_rule(self, can_assign)?;
+
+        while precedence <= Self::get_rule(self.current().ty()).precedence {
+            self.advance()?;
+            let infix_rule = Self::get_rule(self.previous().ty())
+                .infix
+                .expect("operator with a precedence has an infix rule");
+            infix_rule(self, can_assign)?;
+        }
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            self.error("Invalid assignment target.");
+        }
+
+        Ok(())
+    }
+
+    fn get_rule(ty: TokenType) -> ParseRule<'a> {
+        use Precedence as P;
+        match ty {
+            TokenType::LeftParen => ParseRule::new(Some(Self::grouping), Some(Self::call), P::Call),
+            TokenType::Minus => ParseRule::new(Some(Self::unary), Some(Self::binary), P::Term),
+            TokenType::Plus => ParseRule::new(None, Some(Self::binary), P::Term),
+            TokenType::Slash => ParseRule::new(None, Some(Self::binary), P::Factor),
+            TokenType::Star => ParseRule::new(None, Some(Self::binary), P::Factor),
+            TokenType::Bang => ParseRule::new(Some(Self::unary), None, P::None),
+            TokenType::BangEqual => ParseRule::new(None, Some(Self::binary), P::Equality),
+            TokenType::EqualEqual => ParseRule::new(None, Some(Self::binary), P::Equality),
+            TokenType::Greater => ParseRule::new(None, Some(Self::binary), P::Comparison),
+            TokenType::GreaterEqual => ParseRule::new(None, Some(Self::binary), P::Comparison),
+            TokenType::Less => ParseRule::new(None, Some(Self::binary), P::Comparison),
+            TokenType::LessEqual => ParseRule::new(None, Some(Self::binary), P::Comparison),
+            TokenType::Dot => ParseRule::new(None, Some(Self::dot), P::Call),
+            TokenType::Identifier => ParseRule::new(Some(Self::variable), None, P::None),
+            TokenType::Super => ParseRule::new(Some(Self::super_), None, P::None),
+            TokenType::This => ParseRule::new(Some(Self::this), None, P::None),
+            TokenType::And => ParseRule::new(None, Some(Self::and), P::And),
+            TokenType::Or => ParseRule::new(None, Some(Self::or), P::Or),
+            TokenType::String => ParseRule::new(Some(Self::string), None, P::None),
+            TokenType::Number => ParseRule::new(Some(Self::number), None, P::None),
+            TokenType::False => ParseRule::new(Some(Self::literal), None, P::None),
+            TokenType::Nil => ParseRule::new(Some(Self::literal), None, P::None),
+            TokenType::True => ParseRule::new(Some(Self::literal), None, P::None),
+            _ => ParseRule::new(None, None, P::None),
+        }
+    }
+
+    /* Finish the innermost function and allocate it on the heap. Also returns
+     * what the function captures, for the enclosing function's Closure
+     * instruction. */
+    fn end_compiler(&mut self) -> (ObjRef, Vec<Upvalue>) {
         self.emit_return();
+
+        let state = self.functions.pop().expect("compiling a function");
+        let function = ObjKind::Function(ObjFunction {
+            arity: state.arity,
+            upvalue_count: state.upvalues.len(),
+            chunk: state.chunk,
+            name: state.name,
+        });
+        let function = self.heap.alloc(function, &[self.roots, &self.functions]);
+        (function, state.upvalues)
+    }
+
+    /* Emit a jump with a placeholder offset and return where the offset is,
+     * so it can be patched once the target is known. */
+    fn emit_jump(&mut self, instruction: OpCode) -> usize {
+        self.emit_byte(instruction);
+        self.emit_bytes(0xff, 0xff);
+        self.current_chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // -2 to adjust for the bytecode for the jump offset itself.
+        let jump = self.current_chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error("Too much code to jump over.");
+            return;
+        };
+
+        let [high, low] = jump.to_be_bytes();
+        self.current_chunk().code[offset] = high;
+        self.current_chunk().code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_byte(OpCode::Loop);
+
+        // +2 to also jump back over the operand of this instruction.
+        let offset = self.current_chunk().code.len() - loop_start + 2;
+        let Ok(offset) = u16::try_from(offset) else {
+            self.error("Loop body too large.");
+            self.emit_bytes(0, 0);
+            return;
+        };
+
+        let [high, low] = offset.to_be_bytes();
+        self.emit_bytes(high, low);
     }
 
+    /* The implicit return at the end of a body, or a bare `return;`. Returns
+     * nil, except that initializers always return the new instance. */
     fn emit_return(&mut self) {
+        if self.function_state().function_type == FunctionType::Initializer {
+            self.emit_bytes(OpCode::GetLocal, 0);
+        } else {
+            self.emit_byte(OpCode::Nil);
+        }
         self.emit_byte(OpCode::Return);
     }
 
-    fn number(&mut self) {
+    fn number(&mut self, _can_assign: bool) -> anyhow::Result<()> {
         let value = str::from_utf8(self.previous().ident())
             .unwrap()
             .parse::<f64>()
             .unwrap();
         self.emit_constant(Value::Double(value));
+
+        Ok(())
     }
 
-    fn emit_constant(&mut self, value: Value) {
-        let constant = self.make_constant(value);
-        self.emit_bytes(OpCode::Constant, constant);
+    fn string(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        // Trim the surrounding quotes.
+        let ident = self.previous().ident();
+        let chars = String::from_utf8_lossy(&ident[1..ident.len() - 1]).into_owned();
+        let string = self.alloc_string(chars);
+        self.emit_constant(Value::Obj(string));
+
+        Ok(())
+    }
+
+    fn variable(&mut self, can_assign: bool) -> anyhow::Result<()> {
+        let name = self.previous.clone().unwrap().ident();
+        self.named_variable(name, can_assign)
+    }
+
+    fn named_variable(&mut self, name: &[u8], can_assign: bool) -> anyhow::Result<()> {
+        let current = self.functions.len() - 1;
+        let (get_op, set_op, arg) = if let Some(slot) = self.resolve_local(current, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot as usize)
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index as usize)
+        } else {
+            (
+                OpCode::GetGlobal,
+                OpCode::SetGlobal,
+                self.identifier_constant(name),
+            )
+        };
+
+        let op = if can_assign && self.matches(TokenType::Equal)? {
+            self.expression()?;
+            set_op
+        } else {
+            get_op
+        };
+        // Globals are named by a constant, which may need the long form.
+        if op.long().is_some() {
+            self.emit_constant_operand(op, arg);
+        } else {
+            self.emit_bytes(op, arg as u8);
+        }
+
+        Ok(())
+    }
+
+    /* The slot of the innermost local with this name in the function at
+     * `function` in the stack of functions being compiled. */
+    fn resolve_local(&mut self, function: usize, name: &[u8]) -> Option<u8> {
+        let (slot, local) = self.functions[function]
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name)?;
+        if local.depth.is_none() {
+            self.error("Can't read local variable in its own initializer.");
+        }
+        Some(slot as u8)
+    }
+
+    /* The upvalue index under which the function at `function` can reach a
+     * local of some enclosing function. Every function in between captures
+     * it too, so it is passed down one closure at a time. */
+    fn resolve_upvalue(&mut self, function: usize, name: &[u8]) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[local as usize].is_captured = true;
+            return Some(self.add_upvalue(function, local, true));
+        }
+
+        let upvalue = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let upvalues = &self.functions[function].upvalues;
+        if let Some(existing) = upvalues
+            .iter()
+            .position(|upvalue| upvalue.index == index && upvalue.is_local == is_local)
+        {
+            return existing as u8;
+        }
+
+        if upvalues.len() == MAX_UPVALUES {
+            self.error("Too many closure variables in function.");
+            return 0;
+        }
+        let upvalues = &mut self.functions[function].upvalues;
+        upvalues.push(Upvalue { index, is_local });
+        (upvalues.len() - 1) as u8
+    }
+
+    /* Consume a variable name. Globals are looked up by name at runtime, so
+     * they return the constant holding it; locals are declared instead and
+     * return 0. */
+    fn parse_variable(&mut self, message: &str) -> anyhow::Result<usize> {
+        self.consume(TokenType::Identifier, message)?;
+
+        self.declare_variable();
+        if self.function_state().scope_depth > 0 {
+            return Ok(0);
+        }
+
+        let name = self.previous.clone().unwrap().ident();
+        Ok(self.identifier_constant(name))
+    }
+
+    fn declare_variable(&mut self) {
+        let state = self.function_state();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let name = self.previous.clone().unwrap().ident();
+        let duplicate = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth >= state.scope_depth))
+            .any(|local| local.name == name);
+        if duplicate {
+            self.error("Already a variable with this name in this scope.");
+        }
+
+        self.add_local(name);
     }
 
-    fn make_constant(&mut self, value: Value) -> u8 {
+    fn add_local(&mut self, name: &'a [u8]) {
+        if self.function_state().locals.len() == MAX_LOCALS {
+            self.error("Too many local variables in function.");
+            return;
+        }
+        self.function_state_mut().locals.push(Local {
+            name,
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    /* A local becomes usable once its initializer has been compiled. Globals
+     * are always usable, so there is nothing to do for them. */
+    fn mark_initialized(&mut self) {
+        let state = self.function_state_mut();
+        if state.scope_depth == 0 {
+            return;
+        }
+        let depth = state.scope_depth;
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    /* Store a variable's name in the constant table, since it is too big to
+     * fit in an instruction operand. */
+    fn identifier_constant(&mut self, name: &[u8]) -> usize {
+        let chars = String::from_utf8_lossy(name).into_owned();
+        let string = self.alloc_string(chars);
+        self.current_chunk().add_constant(Value::Obj(string))
+    }
+
+    fn alloc_string(&mut self, chars: String) -> ObjRef {
+        self.heap
+            .alloc_string(chars, &[self.roots, &self.functions])
+    }
+
+    fn define_variable(&mut self, global: usize) {
+        // A local's value is already in its slot on top of the stack.
+        if self.function_state().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+        self.emit_constant_operand(OpCode::DefineGlobal, global);
+    }
+
+    fn literal(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        match self.previous().ty() {
+            TokenType::False => self.emit_byte(OpCode::False),
+            TokenType::Nil => self.emit_byte(OpCode::Nil),
+            TokenType::True => self.emit_byte(OpCode::True),
+            ty => unreachable!("literal rule registered for {:?}", ty),
+        }
+
+        Ok(())
+    }
+
+    fn unary(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        let operator_type = self.previous().ty();
+
+        // Compile the operand.
+        self.parse_precedence(Precedence::Unary)?;
+
+        match operator_type {
+            TokenType::Bang => self.emit_byte(OpCode::Not),
+            TokenType::Minus => self.emit_byte(OpCode::Negate),
+            _ => unreachable!("unary rule registered for {:?}", operator_type),
+        }
+
+        Ok(())
+    }
+
+    fn binary(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        let operator_type = self.previous().ty();
+        let rule = Self::get_rule(operator_type);
+        self.parse_precedence(rule.precedence.next())?;
+
+        match operator_type {
+            TokenType::BangEqual => self.emit_bytes(OpCode::Equal, OpCode::Not),
+            TokenType::EqualEqual => self.emit_byte(OpCode::Equal),
+            TokenType::Greater => self.emit_byte(OpCode::Greater),
+            TokenType::GreaterEqual => self.emit_bytes(OpCode::Less, OpCode::Not),
+            TokenType::Less => self.emit_byte(OpCode::Less),
+            TokenType::LessEqual => self.emit_bytes(OpCode::Greater, OpCode::Not),
+            TokenType::Plus => self.emit_byte(OpCode::Add),
+            TokenType::Minus => self.emit_byte(OpCode::Subtract),
+            TokenType::Star => self.emit_byte(OpCode::Multiply),
+            TokenType::Slash => self.emit_byte(OpCode::Divide),
+            _ => unreachable!("binary rule registered for {:?}", operator_type),
+        }
+
+        Ok(())
+    }
+
+    /* If the left operand is falsey it is the result, so skip the right one. */
+    fn and(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit_byte(OpCode::Pop);
+        self.parse_precedence(Precedence::And)?;
+
+        self.patch_jump(end_jump);
+
+        Ok(())
+    }
+
+    /* If the left operand is truthy it is the result, so skip the right one. */
+    fn or(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_byte(OpCode::Pop);
+
+        self.parse_precedence(Precedence::Or)?;
+        self.patch_jump(end_jump);
+
+        Ok(())
+    }
+
+    fn call(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        let arg_count = self.argument_list()?;
+        self.emit_bytes(OpCode::Call, arg_count);
+
+        Ok(())
+    }
+
+    fn dot(&mut self, can_assign: bool) -> anyhow::Result<()> {
+        self.consume(TokenType::Identifier, "Expect property name after '.'.")?;
+        let name = self.previous.clone().unwrap().ident();
+        let name = self.identifier_constant(name);
+
+        if can_assign && self.matches(TokenType::Equal)? {
+            self.expression()?;
+            self.emit_constant_operand(OpCode::SetProperty, name);
+        } else if self.matches(TokenType::LeftParen)? {
+            // Call the method directly instead of creating a bound method.
+            let arg_count = self.argument_list()?;
+            self.emit_constant_operand(OpCode::Invoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.emit_constant_operand(OpCode::GetProperty, name);
+        }
+
+        Ok(())
+    }
+
+    /* `this` is an ordinary local in slot zero of every method. */
+    fn this(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        if self.classes.is_empty() {
+            self.error("Can't use 'this' outside of a class.");
+            return Ok(());
+        }
+        self.variable(false)
+    }
+
+    fn super_(&mut self, _can_assign: bool) -> anyhow::Result<()> {
+        match self.classes.last() {
+            None => self.error("Can't use 'super' outside of a class."),
+            Some(class) if !class.has_superclass => {
+                self.error("Can't use 'super' in a class with no superclass.")
+            }
+            Some(_) => (),
+        }
+
+        self.consume(TokenType::Dot, "Expect '.' after 'super'.")?;
+        self.consume(TokenType::Identifier, "Expect superclass method name.")?;
+        let name = self.previous.clone().unwrap().ident();
+        let name = self.identifier_constant(name);
+
+        self.named_variable(b"this", false)?;
+        if self.matches(TokenType::LeftParen)? {
+            let arg_count = self.argument_list()?;
+            self.named_variable(b"super", false)?;
+            self.emit_constant_operand(OpCode::SuperInvoke, name);
+            self.emit_byte(arg_count);
+        } else {
+            self.named_variable(b"super", false)?;
+            self.emit_constant_operand(OpCode::GetSuper, name);
+        }
+
+        Ok(())
+    }
+
+    fn argument_list(&mut self) -> anyhow::Result<u8> {
+        let mut arg_count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression()?;
+                if arg_count == MAX_ARGS {
+                    self.error("Can't have more than 255 arguments.");
+                }
+                arg_count += 1;
+                if !self.matches(TokenType::Comma)? {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.")?;
+
+        Ok(arg_count.min(MAX_ARGS) as u8)
+    }
+
+    fn emit_constant(&mut self, value: Value) {
         let constant = self.current_chunk().add_constant(value);
-        if constant > u8::MAX as usize {
-            self.error("Too many constants in one chunk.");
-            0
+        self.emit_constant_operand(OpCode::Constant, constant);
+    }
+
+    /* Emit an instruction that takes a constant, switching to its long form
+     * with a 24-bit operand once the pool has outgrown a single byte. */
+    fn emit_constant_operand(&mut self, opcode: OpCode, constant: usize) {
+        if let Ok(constant) = u8::try_from(constant) {
+            self.emit_bytes(opcode, constant);
+        } else if constant < MAX_LONG_CONSTANTS {
+            let [_, high, middle, low] = (constant as u32).to_be_bytes();
+            self.emit_bytes(opcode.long().expect("takes a constant"), high);
+            self.emit_bytes(middle, low);
         } else {
-            constant.try_into().unwrap()
+            self.error("Too many constants in one chunk.");
         }
     }
 
-    fn grouping(&mut self) -> anyhow::Result<()> {
+    fn grouping(&mut self, _can_assign: bool) -> anyhow::Result<()> {
         self.expression()?;
         self.consume(TokenType::RightParen, "Expect ')' after expression.")?;
 
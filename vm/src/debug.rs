@@ -1,52 +1,263 @@
-use crate::chunk::{Chunk, OpCode};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
 
-pub struct Disassembler;
+use crate::{
+    chunk::{Chunk, Instruction, Operand},
+    object::{ObjKind, ObjRef},
+    value::Value,
+};
 
-impl Disassembler {
-    pub fn disassemble(chunk: &Chunk, name: &str) {
-        println!("== {} ==", name);
+/* Writes bytecode in the syntax the assembler reads back. */
+pub struct Disassembler<'s, W> {
+    out: W,
+    /* What each function is called in a whole-program listing. Functions
+     * go by their own name, with a suffix when another already has it. */
+    labels: HashMap<ObjRef, String>,
+    /* The source, by line, when each line is shown above its bytecode. */
+    source: Option<Vec<&'s str>>,
+    /* The last source line shown, so a line is repeated only when the
+     * bytecode comes back to it. */
+    shown_line: Option<usize>,
+}
+
+impl Disassembler<'_, Vec<u8>> {
+    /* A chunk's listing as a string. */
+    pub fn disassemble(chunk: &Chunk, name: &str) -> String {
+        let mut disassembler = Disassembler::new(Vec::new());
+        disassembler
+            .chunk(chunk, name)
+            .expect("writing to a Vec can't fail");
+        disassembler.into_string()
+    }
+
+    /* The listing of a function and every function nested in it. */
+    pub fn disassemble_function(function: ObjRef) -> String {
+        let mut disassembler = Disassembler::new(Vec::new());
+        disassembler
+            .function(function)
+            .expect("writing to a Vec can't fail");
+        disassembler.into_string()
+    }
+
+    fn into_string(self) -> String {
+        String::from_utf8(self.out).expect("listings are UTF-8")
+    }
+}
+
+impl<'s, W: Write> Disassembler<'s, W> {
+    pub fn new(out: W) -> Self {
+        Disassembler {
+            out,
+            labels: HashMap::new(),
+            source: None,
+            shown_line: None,
+        }
+    }
+
+    /* Show each line of `source` as a comment above the bytecode compiled
+     * from it. The listing still assembles. */
+    pub fn annotated(mut self, source: &'s str) -> Self {
+        self.source = Some(source.lines().collect());
+        self
+    }
+
+    pub fn chunk(&mut self, chunk: &Chunk, name: &str) -> io::Result<()> {
+        writeln!(self.out, "== {} ==", name)?;
+        self.code(chunk)
+    }
+
+    /* Write a function and every function nested in it, one section each,
+     * so the assembler can rebuild all of them. */
+    pub fn function(&mut self, function: ObjRef) -> io::Result<()> {
+        let mut counts = HashMap::new();
+        self.label_functions(function, &mut counts);
+        self.section(function)
+    }
+
+    /* Write the instruction at `offset` and return the offset of the next
+     * one. */
+    pub fn instruction(&mut self, chunk: &Chunk, offset: usize) -> io::Result<usize> {
+        match chunk.decode(offset) {
+            Ok(instruction) => {
+                self.write_instruction(chunk, &instruction)?;
+                Ok(offset + instruction.len)
+            }
+            Err(err) => {
+                self.offset_and_line(chunk, offset)?;
+                writeln!(self.out, "<{}>", err.kind)?;
+                Ok(chunk.code.len())
+            }
+        }
+    }
+
+    fn label_functions(&mut self, function: ObjRef, counts: &mut HashMap<String, usize>) {
+        let Some(inner) = function.as_function() else {
+            return;
+        };
+        let name = match inner.name {
+            Some(name) => name.to_string(),
+            None => "<script>".to_owned(),
+        };
+        let count = counts.entry(name.clone()).or_insert(0);
+        *count += 1;
+        let label = match *count {
+            1 => name,
+            n => format!("{}@{}", name, n),
+        };
+        self.labels.insert(function, label);
 
+        for constant in &inner.chunk.constants {
+            if let Value::Obj(obj) = constant {
+                self.label_functions(*obj, counts);
+            }
+        }
+    }
+
+    fn section(&mut self, function: ObjRef) -> io::Result<()> {
+        let Some(inner) = function.as_function() else {
+            return Ok(());
+        };
+        writeln!(self.out, "== {} ==", self.labels[&function])?;
+        if inner.arity > 0 {
+            writeln!(self.out, ".arity {}", inner.arity)?;
+        }
+        if inner.upvalue_count > 0 {
+            writeln!(self.out, ".upvalues {}", inner.upvalue_count)?;
+        }
+        self.code(&inner.chunk)?;
+
+        for constant in &inner.chunk.constants {
+            if let Value::Obj(obj) = constant {
+                if obj.as_function().is_some() {
+                    writeln!(self.out)?;
+                    self.section(*obj)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn code(&mut self, chunk: &Chunk) -> io::Result<()> {
+        self.shown_line = None;
         let mut offset = 0;
         while offset < chunk.code.len() {
-            offset = Self::disassemble_instruction(chunk, offset);
+            offset = self.instruction(chunk, offset)?;
         }
+        Ok(())
     }
 
-    pub fn disassemble_instruction(chunk: &Chunk, offset: usize) -> usize {
-        print!("{:04} ", offset);
-        if offset != 0 && chunk.lines[offset] == chunk.lines[offset - 1] {
-            print!("   | ");
-        } else {
-            print!("{:4} ", chunk.lines[offset]);
-        }
-        let instruction = chunk.code[offset];
-        if let Ok(opcode) = instruction.try_into() {
-            match opcode {
-                OpCode::Constant => Self::constant_instruction("CONSTANT", chunk, offset),
-                OpCode::Add => Self::simple_instruction("ADD", offset),
-                OpCode::Subtract => Self::simple_instruction("SUBTRACT", offset),
-                OpCode::Multiply => Self::simple_instruction("MULTIPLY", offset),
-                OpCode::Divide => Self::simple_instruction("DIVIDE", offset),
-                OpCode::Negate => Self::simple_instruction("NEGATE", offset),
-                OpCode::Return => Self::simple_instruction("RETURN", offset),
+    fn write_instruction(&mut self, chunk: &Chunk, instruction: &Instruction) -> io::Result<()> {
+        let offset = instruction.offset;
+        self.annotate(chunk.line(offset))?;
+        self.offset_and_line(chunk, offset)?;
+
+        let name = instruction.opcode.mnemonic();
+        match &instruction.operand {
+            Operand::None => writeln!(self.out, "{}", name),
+            // A stack slot, upvalue or argument count rather than a constant.
+            Operand::Byte(byte) => writeln!(self.out, "{:16} {:4}", name, byte),
+            Operand::Constant(constant) => writeln!(
+                self.out,
+                "{:16} {:4} {}",
+                name,
+                constant,
+                self.literal(chunk, *constant)
+            ),
+            // A jump shows its source and target offsets.
+            Operand::Jump(target) => writeln!(self.out, "{:16} {:4} -> {}", name, offset, target),
+            Operand::Invoke {
+                constant,
+                arg_count,
+            } => writeln!(
+                self.out,
+                "{:16} ({} args) {:4} {}",
+                name,
+                arg_count,
+                constant,
+                self.literal(chunk, *constant)
+            ),
+            // A closure is followed by an (is_local, index) pair per upvalue.
+            Operand::Closure { constant, upvalues } => {
+                writeln!(
+                    self.out,
+                    "{:16} {:4} {}",
+                    name,
+                    constant,
+                    self.literal(chunk, *constant)
+                )?;
+                let pairs = offset + instruction.len - 2 * upvalues.len();
+                for (i, capture) in upvalues.iter().enumerate() {
+                    self.offset_and_line(chunk, pairs + 2 * i)?;
+                    let kind = if capture.is_local { "local" } else { "upvalue" };
+                    writeln!(self.out, "{:16} {:4}", kind, capture.index)?;
+                }
+                Ok(())
             }
+        }
+    }
+
+    /* Show the source line an instruction came from, when annotating and
+     * the previous instruction came from a different one. */
+    fn annotate(&mut self, line: usize) -> io::Result<()> {
+        let Some(source) = &self.source else {
+            return Ok(());
+        };
+        if self.shown_line == Some(line) {
+            return Ok(());
+        }
+        self.shown_line = Some(line);
+        match source.get(line.wrapping_sub(1)) {
+            Some(text) => writeln!(self.out, "; {:4}: {}", line, text.trim()),
+            None => Ok(()),
+        }
+    }
+
+    /* The offset, then the line, or a bar when it's the same as the byte
+     * before. */
+    fn offset_and_line(&mut self, chunk: &Chunk, offset: usize) -> io::Result<()> {
+        write!(self.out, "{:04} ", offset)?;
+        if offset != 0 && chunk.line(offset) == chunk.line(offset - 1) {
+            write!(self.out, "   | ")
         } else {
-            println!("Unknown opcode {}", instruction);
-            offset + 1
+            write!(self.out, "{:4} ", chunk.line(offset))
         }
     }
 
-    pub fn simple_instruction(name: &str, offset: usize) -> usize {
-        println!("{}", name);
-        offset + 1
+    /* A constant written so the assembler can tell what type it is:
+     * strings are quoted, and functions go by their label. */
+    fn literal(&self, chunk: &Chunk, constant: usize) -> String {
+        let Some(&value) = chunk.constants.get(constant) else {
+            return "<missing constant>".to_owned();
+        };
+        let Value::Obj(obj) = value else {
+            return value.to_string();
+        };
+        match &obj.kind {
+            ObjKind::String(string) => quote(&string.chars),
+            ObjKind::Function(_) => match self.labels.get(&obj) {
+                Some(label) => format!("<fn {}>", label),
+                None => obj.to_string(),
+            },
+            _ => obj.to_string(),
+        }
     }
+}
 
-    pub fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-        let constant = chunk.code[offset + 1];
-        println!(
-            "{:16} {:4} '{}'",
-            name, constant as u8, chunk.constants[constant as usize]
-        );
-        offset + 2
+/* Lox strings can hold newlines, which would break a listing's lines. */
+pub fn quote(chars: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in chars.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
     }
+    quoted.push('"');
+    quoted
 }
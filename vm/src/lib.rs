@@ -1,6 +1,14 @@
+pub mod assembler;
 pub mod chunk;
+pub mod compiler;
 pub mod debug;
+pub mod loxc;
+pub mod memory;
+pub mod object;
+pub mod scanner;
+pub mod suggest;
+pub mod table;
+pub mod trace;
 pub mod value;
+pub mod verify;
 pub mod vm;
-pub mod compiler;
-pub mod scanner;
\ No newline at end of file
@@ -0,0 +1,239 @@
+/* The .loxc format: a compiled script saved to disk so it can be run
+ * without scanning and compiling the source again.
+ *
+ * All integers are little-endian. A file is the magic bytes and a format
+ * version followed by the script function. A function is its arity,
+ * upvalue count, code, line table and constant pool, then its name.
+ * Constants are tagged values; function constants nest recursively. */
+
+use std::io::{Read, Write};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    chunk::Chunk,
+    memory::{FunctionBuilder, Heap, Trace},
+    object::{ObjKind, ObjRef},
+    value::Value,
+};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/* Bump whenever the encoding or the instruction set changes. */
+pub const VERSION: u16 = 2;
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+/* Write a compiled script function, and every function nested in it. */
+pub fn write(function: ObjRef, out: &mut impl Write) -> anyhow::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_all(&VERSION.to_le_bytes())?;
+    write_function(function, out)
+}
+
+fn write_function(function: ObjRef, out: &mut impl Write) -> anyhow::Result<()> {
+    let function = function.as_function().context("Expected a function.")?;
+    write_len(function.arity, out)?;
+    write_len(function.upvalue_count, out)?;
+
+    let chunk = &function.chunk;
+    write_len(chunk.code.len(), out)?;
+    out.write_all(&chunk.code)?;
+
+    let runs: Vec<(usize, usize)> = chunk.lines.runs().collect();
+    write_len(runs.len(), out)?;
+    for (start, line) in runs {
+        write_len(start, out)?;
+        write_len(line, out)?;
+    }
+
+    write_len(chunk.constants.len(), out)?;
+    for constant in &chunk.constants {
+        write_constant(*constant, out)?;
+    }
+
+    match function.name {
+        Some(name) => {
+            out.write_all(&[1])?;
+            write_string(name, out)
+        }
+        None => Ok(out.write_all(&[0])?),
+    }
+}
+
+fn write_constant(constant: Value, out: &mut impl Write) -> anyhow::Result<()> {
+    match constant {
+        Value::Nil => out.write_all(&[TAG_NIL])?,
+        Value::Bool(false) => out.write_all(&[TAG_FALSE])?,
+        Value::Bool(true) => out.write_all(&[TAG_TRUE])?,
+        Value::Double(n) => {
+            out.write_all(&[TAG_NUMBER])?;
+            out.write_all(&n.to_bits().to_le_bytes())?;
+        }
+        Value::Obj(obj) => match &obj.kind {
+            ObjKind::String(_) => {
+                out.write_all(&[TAG_STRING])?;
+                write_string(obj, out)?;
+            }
+            ObjKind::Function(_) => {
+                out.write_all(&[TAG_FUNCTION])?;
+                write_function(obj, out)?;
+            }
+            _ => bail!("Can't save constant '{}'.", obj),
+        },
+    }
+    Ok(())
+}
+
+fn write_string(string: ObjRef, out: &mut impl Write) -> anyhow::Result<()> {
+    let chars = &string.as_string().context("Expected a string.")?.chars;
+    write_len(chars.len(), out)?;
+    Ok(out.write_all(chars.as_bytes())?)
+}
+
+fn write_len(len: usize, out: &mut impl Write) -> anyhow::Result<()> {
+    let len = u32::try_from(len).context("Value too large for a .loxc file.")?;
+    Ok(out.write_all(&len.to_le_bytes())?)
+}
+
+/* Reads a .loxc file back into heap objects. */
+pub struct Loader<'a, R> {
+    input: R,
+    builder: FunctionBuilder<'a>,
+}
+
+impl<'a, R: Read> Loader<'a, R> {
+    pub fn new(input: R, heap: &'a mut Heap, roots: &'a dyn Trace) -> Self {
+        Loader {
+            input,
+            builder: FunctionBuilder::new(heap, roots),
+        }
+    }
+
+    /* Read a whole file and return its script function. */
+    pub fn load(&mut self) -> anyhow::Result<ObjRef> {
+        let mut magic = [0; 4];
+        self.input
+            .read_exact(&mut magic)
+            .context("Not a .loxc file.")?;
+        if &magic != MAGIC {
+            bail!("Not a .loxc file.");
+        }
+
+        let mut version = [0; 2];
+        self.input
+            .read_exact(&mut version)
+            .context("Unexpected end of .loxc file.")?;
+        let version = u16::from_le_bytes(version);
+        if version != VERSION {
+            bail!(
+                "Unsupported .loxc version {} (expected {}).",
+                version,
+                VERSION
+            );
+        }
+
+        self.read_function()
+    }
+
+    fn read_function(&mut self) -> anyhow::Result<ObjRef> {
+        let arity = self.read_len()?;
+        let upvalue_count = self.read_len()?;
+
+        let mut chunk = Chunk::new();
+        let code_len = self.read_len()?;
+        chunk.code = self.read_bytes(code_len)?;
+        self.read_lines(&mut chunk)?;
+        self.builder.begin(chunk)?;
+
+        let constant_count = self.read_len()?;
+        for _ in 0..constant_count {
+            let constant = self.read_constant()?;
+            self.builder.push_constant(constant);
+        }
+
+        let name = match self.read_u8()? {
+            0 => None,
+            1 => Some(self.read_string()?),
+            flag => bail!("Invalid function name flag {}.", flag),
+        };
+        Ok(self.builder.finish(arity, upvalue_count, name))
+    }
+
+    fn read_lines(&mut self, chunk: &mut Chunk) -> anyhow::Result<()> {
+        let run_count = self.read_len()?;
+        let mut runs = Vec::new();
+        for _ in 0..run_count {
+            runs.push((self.read_len()?, self.read_len()?));
+        }
+
+        let code_len = chunk.code.len();
+        if runs.first().is_some_and(|&(start, _)| start != 0) || (runs.is_empty() && code_len != 0)
+        {
+            bail!("Line table doesn't cover the code.");
+        }
+        for (i, &(start, line)) in runs.iter().enumerate() {
+            let end = runs.get(i + 1).map_or(code_len, |&(next, _)| next);
+            if end <= start || end > code_len {
+                bail!("Line table doesn't cover the code.");
+            }
+            for _ in start..end {
+                chunk.lines.push(line);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_constant(&mut self) -> anyhow::Result<Value> {
+        Ok(match self.read_u8()? {
+            TAG_NIL => Value::Nil,
+            TAG_FALSE => Value::Bool(false),
+            TAG_TRUE => Value::Bool(true),
+            TAG_NUMBER => {
+                let mut bits = [0; 8];
+                self.input
+                    .read_exact(&mut bits)
+                    .context("Unexpected end of .loxc file.")?;
+                Value::Double(f64::from_bits(u64::from_le_bytes(bits)))
+            }
+            TAG_STRING => Value::Obj(self.read_string()?),
+            TAG_FUNCTION => Value::Obj(self.read_function()?),
+            tag => bail!("Invalid constant tag {}.", tag),
+        })
+    }
+
+    fn read_string(&mut self) -> anyhow::Result<ObjRef> {
+        let len = self.read_len()?;
+        let chars = String::from_utf8(self.read_bytes(len)?).context("Invalid string constant.")?;
+        Ok(self.builder.alloc_string(chars))
+    }
+
+    fn read_bytes(&mut self, len: usize) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.input).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            bail!("Unexpected end of .loxc file.");
+        }
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> anyhow::Result<usize> {
+        let mut bytes = [0; 4];
+        self.input
+            .read_exact(&mut bytes)
+            .context("Unexpected end of .loxc file.")?;
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn read_u8(&mut self) -> anyhow::Result<u8> {
+        let mut byte = [0];
+        self.input
+            .read_exact(&mut byte)
+            .context("Unexpected end of .loxc file.")?;
+        Ok(byte[0])
+    }
+}
@@ -1,18 +1,94 @@
-use std::{env::args, io::stdin, path::Path};
-
-use vm::{compiler::Compiler, vm::Vm};
+use std::{
+    fs::File,
+    io::{stderr, stdin, stdout, BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+use vm::{
+    debug::Disassembler,
+    loxc,
+    memory::GcConfig,
+    trace::Tracer,
+    vm::{InterpretError, Vm},
+};
+
+const USAGE: &str =
+    "Usage: clox [--gc-stress] [--gc-growth=<factor>] [--gc-stats] [--compile=<out.loxc>] [--disassemble] [--annotate] [--trace[=<file>]] [--trace-fn=<name>] [--trace-lines=<from>[-<to>]] [path]";
 
 fn main() -> anyhow::Result<()> {
-    let args: Vec<_> = args().collect();
-    match args.len() {
-        1 => repl()?,
-        2 => run_file(Path::new(&args[1])).map(|_| ())?,
-        _ => {
-            eprintln!("Usage: clox [path]");
-            std::process::exit(64);
+    let mut gc_config = GcConfig::default();
+    let mut gc_stats = false;
+    let mut compile_to = None;
+    let mut disassemble = false;
+    let mut annotate = false;
+    // Tracing is on if any of these are given.
+    let mut trace_to = None;
+    let mut trace_functions = Vec::new();
+    let mut trace_lines = None;
+    let mut args = Vec::new();
+    for arg in std::env::args() {
+        if arg == "--gc-stress" {
+            gc_config.stress = true;
+        } else if arg == "--gc-stats" {
+            gc_stats = true;
+        } else if arg == "--disassemble" {
+            disassemble = true;
+        } else if arg == "--annotate" {
+            disassemble = true;
+            annotate = true;
+        } else if arg == "--trace" {
+            trace_to.get_or_insert(None);
+        } else if let Some(out) = arg.strip_prefix("--trace=") {
+            trace_to = Some(Some(out.to_owned()));
+        } else if let Some(name) = arg.strip_prefix("--trace-fn=") {
+            trace_functions.push(name.to_owned());
+        } else if let Some(lines) = arg.strip_prefix("--trace-lines=") {
+            trace_lines = Some(parse_lines(lines).unwrap_or_else(|| usage()));
+        } else if let Some(out) = arg.strip_prefix("--compile=") {
+            compile_to = Some(out.to_owned());
+        } else if let Some(factor) = arg.strip_prefix("--gc-growth=") {
+            match factor.parse() {
+                Ok(factor) if factor > 1 => gc_config.growth_factor = factor,
+                _ => usage(),
+            }
+        } else {
+            args.push(arg);
         }
     }
 
+    let mut vm = Vm::with_gc_config(gc_config);
+    if trace_to.is_some() || !trace_functions.is_empty() || trace_lines.is_some() {
+        // The trace goes to stderr by default, to keep it out of the
+        // program's own output.
+        let mut tracer = match trace_to.flatten() {
+            Some(path) => Tracer::new(BufWriter::new(File::create(path)?)),
+            None => Tracer::new(stderr()),
+        }
+        .functions(trace_functions);
+        if let Some(lines) = trace_lines {
+            tracer = tracer.lines(lines);
+        }
+        vm.set_tracer(Some(tracer));
+    }
+    match (args.len(), compile_to) {
+        (1, None) if !disassemble => repl(&mut vm)?,
+        (2, None) if disassemble => disassemble_file(&mut vm, Path::new(&args[1]), annotate)?,
+        (2, None) => run_file(&mut vm, Path::new(&args[1]))?,
+        (2, Some(out)) if !disassemble => {
+            compile_file(&mut vm, Path::new(&args[1]), Path::new(&out))?
+        }
+        _ => usage(),
+    }
+
+    if gc_stats {
+        let stats = vm.gc_stats();
+        eprintln!(
+            "gc: {} collections, {} bytes allocated, {} bytes freed",
+            stats.collections, stats.bytes_allocated, stats.bytes_freed
+        );
+    }
+
     Ok(())
 
     // let mut chunk = Chunk::new();
@@ -40,28 +116,93 @@ fn main() -> anyhow::Result<()> {
     // vm.interpret(&chunk).unwrap();
 }
 
-fn repl() -> anyhow::Result<()> {
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    std::process::exit(64);
+}
+
+fn repl(vm: &mut Vm) -> anyhow::Result<()> {
     loop {
         let mut line = String::new();
         if stdin().read_line(&mut line).unwrap() == 0 {
             break Ok(());
         }
-        interpret(line.as_bytes())?;
+        // Errors have already been reported, and the session goes on.
+        if let Err(err) = vm.interpret(line.as_bytes()) {
+            if err.downcast_ref::<InterpretError>().is_none() {
+                return Err(err);
+            }
+        }
     }
 }
 
-struct InterpretResult;
+/* Run a Lox source file, a script compiled with --compile, or an assembly
+ * listing. */
+fn run_file(vm: &mut Vm, path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let source = std::fs::read(path)?;
+    let result = if source.starts_with(loxc::MAGIC) {
+        vm.interpret_compiled(source.as_slice())
+    } else if is_assembly(&source) {
+        vm.interpret_assembly(std::str::from_utf8(&source)?)
+    } else {
+        vm.interpret(&source)
+    };
+    exit_on_error(result)
+}
 
-fn interpret(source: &[u8]) -> anyhow::Result<InterpretResult> {
-    let mut compiler = Compiler::new(source);
-    let chunk = compiler.compile()?;
-    let mut vm = Vm::new();
-    vm.interpret(&chunk)?;
+/* Print the bytecode for any file run_file accepts, in a listing that
+ * can be edited and run again. Listings of Lox source can show each line
+ * above its bytecode. */
+fn disassemble_file(vm: &mut Vm, path: impl AsRef<Path>, annotate: bool) -> anyhow::Result<()> {
+    let source = std::fs::read(path)?;
+    let (script, lox_source) = if source.starts_with(loxc::MAGIC) {
+        (vm.load_compiled(source.as_slice()), None)
+    } else if is_assembly(&source) {
+        (vm.assemble(std::str::from_utf8(&source)?), None)
+    } else {
+        (vm.compile(&source), Some(String::from_utf8_lossy(&source)))
+    };
+    let script = exit_on_error(script)?;
+
+    let mut disassembler = Disassembler::new(stdout().lock());
+    if let (true, Some(source)) = (annotate, &lox_source) {
+        disassembler = disassembler.annotated(source);
+    }
+    Ok(disassembler.function(script.function())?)
+}
 
-    Ok(InterpretResult)
+/* A line number, or an inclusive range of them like `10-20`. */
+fn parse_lines(lines: &str) -> Option<RangeInclusive<usize>> {
+    match lines.split_once('-') {
+        Some((from, to)) => Some(from.parse().ok()?..=to.parse().ok()?),
+        None => {
+            let line = lines.parse().ok()?;
+            Some(line..=line)
+        }
+    }
 }
 
-fn run_file(path: impl AsRef<Path>) -> anyhow::Result<InterpretResult> {
-    let source = std::fs::read_to_string(path).unwrap();
-    interpret(source.as_bytes())
+/* Listings start with a section header, which Lox source never does. */
+fn is_assembly(source: &[u8]) -> bool {
+    source.trim_ascii_start().starts_with(b"==")
+}
+
+fn compile_file(vm: &mut Vm, path: &Path, out: &Path) -> anyhow::Result<()> {
+    let source = std::fs::read(path)?;
+    let mut out = BufWriter::new(File::create(out)?);
+    exit_on_error(vm.compile_to(&source, &mut out))?;
+    Ok(out.flush()?)
+}
+
+/* Exit with the conventional status for compile and runtime errors, which
+ * have already been reported. */
+fn exit_on_error<T>(result: anyhow::Result<T>) -> anyhow::Result<T> {
+    match result {
+        Err(err) => match err.downcast_ref::<InterpretError>() {
+            Some(InterpretError::Compile) => std::process::exit(65),
+            Some(InterpretError::Runtime) => std::process::exit(70),
+            None => Err(err),
+        },
+        Ok(value) => Ok(value),
+    }
 }
@@ -0,0 +1,332 @@
+use std::{collections::VecDeque, ptr::NonNull};
+
+use anyhow::bail;
+
+use crate::{
+    chunk::Chunk,
+    object::{hash_string, Obj, ObjFunction, ObjKind, ObjRef, ObjString, UpvalueState},
+    table::Table,
+    value::Value,
+};
+
+/* Anything that can hold references to heap objects. Callers that allocate
+ * pass whatever they hold as roots so a collection keeps it alive. */
+pub trait Trace {
+    fn trace(&self, heap: &mut Heap);
+}
+
+impl Trace for Value {
+    fn trace(&self, heap: &mut Heap) {
+        if let Value::Obj(obj) = self {
+            heap.mark_object(*obj);
+        }
+    }
+}
+
+impl Trace for VecDeque<Value> {
+    fn trace(&self, heap: &mut Heap) {
+        for value in self {
+            value.trace(heap);
+        }
+    }
+}
+
+impl Trace for ObjRef {
+    fn trace(&self, heap: &mut Heap) {
+        heap.mark_object(*self);
+    }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+    fn trace(&self, heap: &mut Heap) {
+        for item in self {
+            item.trace(heap);
+        }
+    }
+}
+
+impl Trace for Chunk {
+    fn trace(&self, heap: &mut Heap) {
+        for constant in &self.constants {
+            constant.trace(heap);
+        }
+    }
+}
+
+/* The objects a heap object refers to. */
+impl Trace for ObjKind {
+    fn trace(&self, heap: &mut Heap) {
+        match self {
+            ObjKind::String(_) => (),
+            ObjKind::Function(function) => {
+                function.chunk.trace(heap);
+                if let Some(name) = function.name {
+                    heap.mark_object(name);
+                }
+            }
+            ObjKind::Closure(closure) => {
+                heap.mark_object(closure.function);
+                closure.upvalues.trace(heap);
+            }
+            ObjKind::Upvalue(upvalue) => {
+                // An open upvalue's variable is still on the stack, which is
+                // a root anyway.
+                if let UpvalueState::Closed(value) = upvalue.state.get() {
+                    value.trace(heap);
+                }
+            }
+            ObjKind::Class(class) => {
+                heap.mark_object(class.name);
+                class.methods.borrow().trace(heap);
+            }
+            ObjKind::Instance(instance) => {
+                heap.mark_object(instance.class);
+                instance.fields.borrow().trace(heap);
+            }
+            ObjKind::BoundMethod(bound) => {
+                bound.receiver.trace(heap);
+                heap.mark_object(bound.method);
+            }
+        }
+    }
+}
+
+impl Trace for Table {
+    fn trace(&self, heap: &mut Heap) {
+        for (key, value) in self.iter() {
+            heap.mark_object(key);
+            value.trace(heap);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcConfig {
+    /* Collect before every allocation. Slow, but flushes out missing roots. */
+    pub stress: bool,
+    /* After a collection the next one is due once the heap grows to this
+     * multiple of what survived. */
+    pub growth_factor: usize,
+    pub initial_threshold: usize,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            stress: false,
+            growth_factor: 2,
+            initial_threshold: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GcStats {
+    /* Total bytes ever allocated, including what has since been freed. */
+    pub bytes_allocated: usize,
+    pub bytes_freed: usize,
+    pub collections: usize,
+}
+
+/* Owns every object the compiler and the vm allocate, and frees the ones
+ * that are no longer reachable with a mark-and-sweep collector. */
+pub struct Heap {
+    objects: Option<ObjRef>,
+    /* Every live string, so equal strings share one object. Held weakly. */
+    strings: Table,
+    config: GcConfig,
+    stats: GcStats,
+    bytes_allocated: usize,
+    next_gc: usize,
+    gray_stack: Vec<ObjRef>,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self::with_config(GcConfig::default())
+    }
+
+    pub fn with_config(config: GcConfig) -> Self {
+        Heap {
+            objects: None,
+            strings: Table::new(),
+            config,
+            stats: GcStats::default(),
+            bytes_allocated: 0,
+            next_gc: config.initial_threshold,
+            gray_stack: Vec::new(),
+        }
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    /* Allocate a new object, first collecting garbage if it is due. Anything
+     * reachable from `roots` or from the new object survives the collection. */
+    pub fn alloc(&mut self, kind: ObjKind, roots: &[&dyn Trace]) -> ObjRef {
+        if self.config.stress || self.bytes_allocated > self.next_gc {
+            let mut roots = roots.to_vec();
+            roots.push(&kind);
+            self.collect_garbage(&roots);
+        }
+
+        let obj = Box::new(Obj::new(self.objects, kind));
+        let size = obj.size();
+        self.bytes_allocated += size;
+        self.stats.bytes_allocated += size;
+
+        let obj = ObjRef::new(NonNull::from(Box::leak(obj)));
+        self.objects = Some(obj);
+        obj
+    }
+
+    /* Return the interned string with these contents, allocating it if needed. */
+    pub fn alloc_string(&mut self, chars: String, roots: &[&dyn Trace]) -> ObjRef {
+        let hash = hash_string(&chars);
+        if let Some(interned) = self.strings.find_string(&chars, hash) {
+            return interned;
+        }
+        let string = self.alloc(ObjKind::String(ObjString { chars, hash }), roots);
+        self.strings.set(string, Value::Nil);
+        string
+    }
+
+    pub fn collect_garbage(&mut self, roots: &[&dyn Trace]) {
+        for root in roots {
+            root.trace(self);
+        }
+        self.trace_references();
+        self.strings.retain(|string| string.is_marked());
+        self.sweep();
+
+        self.next_gc = self.bytes_allocated * self.config.growth_factor;
+        self.stats.collections += 1;
+    }
+
+    pub fn mark_object(&mut self, obj: ObjRef) {
+        if obj.is_marked() {
+            return;
+        }
+        obj.set_marked(true);
+        self.gray_stack.push(obj);
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(obj) = self.gray_stack.pop() {
+            self.blacken_object(obj);
+        }
+    }
+
+    fn blacken_object(&mut self, obj: ObjRef) {
+        obj.kind.trace(self);
+    }
+
+    fn sweep(&mut self) {
+        let mut previous: Option<ObjRef> = None;
+        let mut object = self.objects;
+        while let Some(obj) = object {
+            object = obj.next();
+            if obj.is_marked() {
+                obj.set_marked(false);
+                previous = Some(obj);
+                continue;
+            }
+
+            match previous {
+                Some(previous) => previous.set_next(object),
+                None => self.objects = object,
+            }
+            self.free(obj);
+        }
+    }
+
+    fn free(&mut self, obj: ObjRef) {
+        // SAFETY: every object on the list was leaked from a Box in `alloc`
+        // and is unlinked before it is freed, so this happens exactly once.
+        let obj = unsafe { Box::from_raw(obj.as_ptr()) };
+        let size = obj.size();
+        self.bytes_allocated -= size;
+        self.stats.bytes_freed += size;
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        let mut object = self.objects.take();
+        while let Some(obj) = object {
+            object = obj.next();
+            self.free(obj);
+        }
+    }
+}
+
+/* How deeply functions can nest, counting the script. Reading functions
+ * back in, verifying them and disassembling them all recurse once per
+ * level, so without a limit a crafted file could overflow the stack. */
+pub const MAX_FUNCTION_DEPTH: usize = 256;
+
+/* Builds functions whose constants are made one at a time, some of them
+ * allocated along the way, as when reading bytecode back in. Constants may
+ * be functions that are built in turn, so the chunks being filled form a
+ * stack, and they stay where the collector can see them until the
+ * function that owns each one is allocated. */
+pub struct FunctionBuilder<'a> {
+    heap: &'a mut Heap,
+    /* What the caller keeps alive while the builder allocates. */
+    roots: &'a dyn Trace,
+    /* The chunks of the functions being built, innermost last. */
+    chunks: Vec<Chunk>,
+}
+
+impl<'a> FunctionBuilder<'a> {
+    pub fn new(heap: &'a mut Heap, roots: &'a dyn Trace) -> Self {
+        FunctionBuilder {
+            heap,
+            roots,
+            chunks: Vec::new(),
+        }
+    }
+
+    /* Start a function with its code and lines. It is built inside the
+     * function started before it, until it is finished. */
+    pub fn begin(&mut self, chunk: Chunk) -> anyhow::Result<()> {
+        if self.chunks.len() == MAX_FUNCTION_DEPTH {
+            bail!("Functions nested too deeply.");
+        }
+        self.chunks.push(chunk);
+        Ok(())
+    }
+
+    /* Add the next constant of the innermost function. Not add_constant:
+     * indices in the code must stay as they were written. */
+    pub fn push_constant(&mut self, constant: Value) {
+        self.chunks
+            .last_mut()
+            .expect("a function has begun")
+            .constants
+            .push(constant);
+    }
+
+    pub fn alloc_string(&mut self, chars: String) -> ObjRef {
+        self.heap.alloc_string(chars, &[self.roots, &self.chunks])
+    }
+
+    /* Allocate the innermost function. */
+    pub fn finish(&mut self, arity: usize, upvalue_count: usize, name: Option<ObjRef>) -> ObjRef {
+        let chunk = self.chunks.pop().expect("a function has begun");
+        let function = ObjKind::Function(ObjFunction {
+            arity,
+            upvalue_count,
+            chunk,
+            name,
+        });
+        self.heap.alloc(function, &[self.roots, &self.chunks])
+    }
+}
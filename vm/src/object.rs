@@ -0,0 +1,222 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Display,
+    mem,
+    ops::Deref,
+    ptr::NonNull,
+};
+
+use crate::{chunk::Chunk, table::Table, value::Value};
+
+/* A heap-allocated object. Every object lives on the `Heap`'s intrusive list
+ * until the collector frees it. */
+pub struct Obj {
+    is_marked: Cell<bool>,
+    next: Cell<Option<ObjRef>>,
+    pub kind: ObjKind,
+}
+
+impl Obj {
+    pub(crate) fn new(next: Option<ObjRef>, kind: ObjKind) -> Self {
+        Obj {
+            is_marked: Cell::new(false),
+            next: Cell::new(next),
+            kind,
+        }
+    }
+
+    pub(crate) fn is_marked(&self) -> bool {
+        self.is_marked.get()
+    }
+
+    pub(crate) fn set_marked(&self, is_marked: bool) {
+        self.is_marked.set(is_marked);
+    }
+
+    pub(crate) fn next(&self) -> Option<ObjRef> {
+        self.next.get()
+    }
+
+    pub(crate) fn set_next(&self, next: Option<ObjRef>) {
+        self.next.set(next);
+    }
+
+    /* Bytes this object accounts for on the heap. Only counts storage that
+     * doesn't change size after allocation, so it is the same when freed. */
+    pub(crate) fn size(&self) -> usize {
+        let owned = match &self.kind {
+            ObjKind::String(string) => string.chars.capacity(),
+            ObjKind::Function(function) => {
+                let chunk = &function.chunk;
+                chunk.code.capacity()
+                    + chunk.constants.capacity() * mem::size_of::<Value>()
+                    + chunk.lines.capacity_bytes()
+            }
+            ObjKind::Closure(closure) => closure.upvalues.capacity() * mem::size_of::<ObjRef>(),
+            // Method and field tables grow after allocation, so they aren't
+            // counted.
+            ObjKind::Upvalue(_)
+            | ObjKind::Class(_)
+            | ObjKind::Instance(_)
+            | ObjKind::BoundMethod(_) => 0,
+        };
+        mem::size_of::<Obj>() + owned
+    }
+}
+
+pub enum ObjKind {
+    String(ObjString),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+pub struct ObjString {
+    pub chars: String,
+    pub hash: u32,
+}
+
+/* A compiled function. Its chunk is finished before the function is
+ * allocated and never changes afterwards. */
+pub struct ObjFunction {
+    pub arity: usize,
+    /* How many variables the function captures from enclosing functions. */
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /* None for the implicit function wrapping top-level code. */
+    pub name: Option<ObjRef>,
+}
+
+/* A function together with the variables it captured when it was created. */
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+/* A captured variable. It refers to the variable's stack slot until the
+ * variable goes out of scope, and then holds the value itself. */
+pub struct ObjUpvalue {
+    pub state: Cell<UpvalueState>,
+}
+
+#[derive(Clone, Copy)]
+pub enum UpvalueState {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct ObjClass {
+    pub name: ObjRef,
+    pub methods: RefCell<Table>,
+}
+
+pub struct ObjInstance {
+    pub class: ObjRef,
+    pub fields: RefCell<Table>,
+}
+
+/* A method closure looked up on an instance, remembering the instance to
+ * bind `this` to when it is called. */
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+/* FNV-1a. */
+pub fn hash_string(chars: &str) -> u32 {
+    let mut hash = 2166136261u32;
+    for byte in chars.bytes() {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(16777619);
+    }
+    hash
+}
+
+/* A pointer to an object owned by the `Heap`. References stay valid for as
+ * long as the heap that allocated them keeps the object alive. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(NonNull<Obj>);
+
+impl ObjRef {
+    pub(crate) fn new(obj: NonNull<Obj>) -> Self {
+        ObjRef(obj)
+    }
+
+    pub(crate) fn as_ptr(self) -> *mut Obj {
+        self.0.as_ptr()
+    }
+
+    pub fn as_string(&self) -> Option<&ObjString> {
+        match &self.kind {
+            ObjKind::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_function(&self) -> Option<&ObjFunction> {
+        match &self.kind {
+            ObjKind::Function(function) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub fn as_closure(&self) -> Option<&ObjClosure> {
+        match &self.kind {
+            ObjKind::Closure(closure) => Some(closure),
+            _ => None,
+        }
+    }
+
+    pub fn as_upvalue(&self) -> Option<&ObjUpvalue> {
+        match &self.kind {
+            ObjKind::Upvalue(upvalue) => Some(upvalue),
+            _ => None,
+        }
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match &self.kind {
+            ObjKind::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match &self.kind {
+            ObjKind::Instance(instance) => Some(instance),
+            _ => None,
+        }
+    }
+}
+
+impl Deref for ObjRef {
+    type Target = Obj;
+
+    fn deref(&self) -> &Obj {
+        // SAFETY: the heap keeps every object it hands out a reference to alive.
+        unsafe { self.0.as_ref() }
+    }
+}
+
+impl Display for ObjRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ObjKind::String(string) => write!(f, "{}", string.chars),
+            ObjKind::Function(function) => match function.name {
+                Some(name) => write!(f, "<fn {}>", name),
+                None => write!(f, "<script>"),
+            },
+            ObjKind::Closure(closure) => write!(f, "{}", closure.function),
+            ObjKind::Upvalue(_) => write!(f, "upvalue"),
+            ObjKind::Class(class) => write!(f, "{}", class.name),
+            ObjKind::Instance(instance) => {
+                let class = instance.class.as_class().expect("class");
+                write!(f, "{} instance", class.name)
+            }
+            ObjKind::BoundMethod(bound) => write!(f, "{}", bound.method),
+        }
+    }
+}
@@ -36,6 +36,7 @@ impl<'a> Scanner<'a> {
             '+' => self.make_token(TokenType::Plus),
             ';' => self.make_token(TokenType::Semicolon),
             '*' => self.make_token(TokenType::Star),
+            '/' => self.make_token(TokenType::Slash),
             '!' => {
                 if self.matches('=') {
                     self.make_token(TokenType::BangEqual)
@@ -72,11 +73,11 @@ impl<'a> Scanner<'a> {
     }
 
     fn is_digit(&self, c: char) -> bool {
-        ('0'..='9').contains(&c)
+        c.is_ascii_digit()
     }
 
     fn is_alpha(&self, c: char) -> bool {
-        ('a'..='z').contains(&c) || ('A'..='Z').contains(&c) || c == '_'
+        c.is_ascii_lowercase() || c.is_ascii_uppercase() || c == '_'
     }
 
     fn is_alphanumeric(&self, c: char) -> bool {
@@ -85,7 +86,7 @@ impl<'a> Scanner<'a> {
 
     fn advance(&mut self) -> char {
         let c = self.current.get_ref()[self.current.position() as usize];
-        self.current.set_position(self.current.position() - 1);
+        self.current.set_position(self.current.position() + 1);
         c as char
     }
 
@@ -137,7 +138,7 @@ impl<'a> Scanner<'a> {
                             self.advance();
                         }
                     } else {
-                        self.make_token(TokenType::Slash);
+                        break;
                     }
                 }
                 _ => break,
@@ -146,7 +147,11 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek(&self) -> char {
-        self.current.get_ref()[self.current.position() as usize] as char
+        if self.is_at_end() {
+            '\0'
+        } else {
+            self.current.get_ref()[self.current.position() as usize] as char
+        }
     }
 
     fn identifier(&mut self) -> Token<'a> {
@@ -167,13 +172,13 @@ impl<'a> Scanner<'a> {
             'i' => self.check_keyword(1, "f", TokenType::If),
             'n' => self.check_keyword(1, "il", TokenType::Nil),
             'o' => self.check_keyword(1, "r", TokenType::Or),
-            'p' => self.check_keyword(1, "print", TokenType::Print),
+            'p' => self.check_keyword(1, "rint", TokenType::Print),
             'r' => self.check_keyword(1, "eturn", TokenType::Return),
             's' => self.check_keyword(1, "uper", TokenType::Super),
             'v' => self.check_keyword(1, "ar", TokenType::Var),
             'w' => self.check_keyword(1, "hile", TokenType::While),
             'f' if self.current.position() - self.start.position() > 1 => {
-                match self.start.get_ref()[self.start.position() as usize] as char {
+                match self.start.get_ref()[self.start.position() as usize + 1] as char {
                     'a' => self.check_keyword(2, "lse", TokenType::False),
                     'o' => self.check_keyword(2, "r", TokenType::For),
                     'u' => self.check_keyword(2, "n", TokenType::Fun),
@@ -181,9 +186,9 @@ impl<'a> Scanner<'a> {
                 }
             }
             't' if self.current.position() - self.start.position() > 1 => {
-                match self.start.get_ref()[self.start.position() as usize] as char {
-                    'h' => self.check_keyword(2, "is", TokenType::False),
-                    'r' => self.check_keyword(2, "ue", TokenType::For),
+                match self.start.get_ref()[self.start.position() as usize + 1] as char {
+                    'h' => self.check_keyword(2, "is", TokenType::This),
+                    'r' => self.check_keyword(2, "ue", TokenType::True),
                     _ => TokenType::Identifier,
                 }
             }
@@ -192,7 +197,8 @@ impl<'a> Scanner<'a> {
     }
 
     fn check_keyword(&self, start: usize, rest: &str, ty: TokenType) -> TokenType {
-        if self.current.position() - self.start.position() == rest.len() as u64
+        let start = self.start.position() as usize + start;
+        if self.current.position() as usize == start + rest.len()
             && &self.start.get_ref()[start..start + rest.len()] == rest.as_bytes()
         {
             ty
@@ -234,10 +240,11 @@ impl<'a> Scanner<'a> {
     }
 
     fn peek_next(&mut self) -> char {
-        if self.is_at_end() {
+        let next = self.current.position() as usize + 1;
+        if next >= self.current.get_ref().len() {
             '\0'
         } else {
-            self.current.get_ref()[self.current.position() as usize] as char
+            self.current.get_ref()[next] as char
         }
     }
 }
@@ -263,12 +270,17 @@ impl<'a> Token<'a> {
         self.length
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
     pub fn line(&self) -> usize {
         self.line
     }
 
-    pub fn ident(&self) -> &[u8] {
-        &self.start.get_ref()[..self.length]
+    pub fn ident(&self) -> &'a [u8] {
+        let start = self.start.position() as usize;
+        &self.start.get_ref()[start..start + self.length]
     }
 
     fn new(ty: TokenType, start: Cursor<&'a [u8]>, length: usize, line: usize) -> Self {
@@ -0,0 +1,166 @@
+use crate::{object::ObjRef, value::Value};
+
+const MAX_LOAD: f64 = 0.75;
+
+#[derive(Clone, Copy)]
+struct Entry {
+    key: Option<ObjRef>,
+    value: Value,
+}
+
+impl Entry {
+    const EMPTY: Entry = Entry {
+        key: None,
+        value: Value::Nil,
+    };
+
+    /* A deleted entry keeps probe sequences passing through it intact. */
+    const TOMBSTONE: Entry = Entry {
+        key: None,
+        value: Value::Bool(true),
+    };
+
+    fn is_tombstone(&self) -> bool {
+        self.key.is_none() && !matches!(self.value, Value::Nil)
+    }
+}
+
+/* An open-addressing hash table keyed by interned strings. Because keys are
+ * interned, two keys are equal exactly when they are the same object. */
+#[derive(Default)]
+pub struct Table {
+    /* Number of live entries plus tombstones. */
+    count: usize,
+    entries: Vec<Entry>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table {
+            count: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, key: ObjRef) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+        let entry = &self.entries[Self::find_entry(&self.entries, key)];
+        entry.key.map(|_| entry.value)
+    }
+
+    /* Insert or overwrite the value for `key`. Returns true if the key is new. */
+    pub fn set(&mut self, key: ObjRef, value: Value) -> bool {
+        if (self.count + 1) as f64 > self.entries.len() as f64 * MAX_LOAD {
+            self.grow();
+        }
+
+        let index = Self::find_entry(&self.entries, key);
+        let entry = &mut self.entries[index];
+        let is_new_key = entry.key.is_none();
+        // Reusing a tombstone doesn't change the count; it was already counted.
+        if is_new_key && !entry.is_tombstone() {
+            self.count += 1;
+        }
+        *entry = Entry {
+            key: Some(key),
+            value,
+        };
+        is_new_key
+    }
+
+    pub fn delete(&mut self, key: ObjRef) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+        let index = Self::find_entry(&self.entries, key);
+        if self.entries[index].key.is_none() {
+            return false;
+        }
+        self.entries[index] = Entry::TOMBSTONE;
+        true
+    }
+
+    pub fn add_all(&self, to: &mut Table) {
+        for entry in &self.entries {
+            if let Some(key) = entry.key {
+                to.set(key, entry.value);
+            }
+        }
+    }
+
+    /* Look up a string by content rather than identity. This is how strings
+     * get interned in the first place. */
+    pub fn find_string(&self, chars: &str, hash: u32) -> Option<ObjRef> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let capacity = self.entries.len();
+        let mut index = hash as usize & (capacity - 1);
+        loop {
+            let entry = &self.entries[index];
+            match entry.key {
+                None if !entry.is_tombstone() => return None,
+                Some(key) => {
+                    let string = key.as_string().expect("table keys are strings");
+                    if string.hash == hash && string.chars == chars {
+                        return Some(key);
+                    }
+                }
+                None => (),
+            }
+            index = (index + 1) & (capacity - 1);
+        }
+    }
+
+    /* Delete every entry whose key fails `keep`. Lets a table hold its keys
+     * weakly, dropping them once nothing else refers to them. */
+    pub fn retain(&mut self, keep: impl Fn(ObjRef) -> bool) {
+        for entry in &mut self.entries {
+            if entry.key.is_some_and(|key| !keep(key)) {
+                *entry = Entry::TOMBSTONE;
+            }
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ObjRef, Value)> + '_ {
+        self.entries
+            .iter()
+            .filter_map(|entry| entry.key.map(|key| (key, entry.value)))
+    }
+
+    fn find_entry(entries: &[Entry], key: ObjRef) -> usize {
+        let capacity = entries.len();
+        let hash = key.as_string().expect("table keys are strings").hash;
+        let mut index = hash as usize & (capacity - 1);
+        let mut tombstone = None;
+        loop {
+            let entry = &entries[index];
+            match entry.key {
+                Some(k) if k == key => return index,
+                Some(_) => (),
+                None if entry.is_tombstone() => {
+                    tombstone.get_or_insert(index);
+                }
+                None => return tombstone.unwrap_or(index),
+            }
+            index = (index + 1) & (capacity - 1);
+        }
+    }
+
+    fn grow(&mut self) {
+        let capacity = (self.entries.len() * 2).max(8);
+        let old = std::mem::replace(&mut self.entries, vec![Entry::EMPTY; capacity]);
+        // Tombstones are dropped when rehashing, so recount.
+        self.count = 0;
+        for entry in old {
+            if let Some(key) = entry.key {
+                let index = Self::find_entry(&self.entries, key);
+                self.entries[index] = entry;
+                self.count += 1;
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+use crate::{debug::Disassembler, object::ObjFunction, value::Value};
+
+/* Writes each instruction the vm runs, after the stack it runs on. The vm
+ * only traces when it is given one of these. */
+pub struct Tracer {
+    out: Box<dyn Write>,
+    /* Only trace functions with these names, when there are any. The top
+     * level goes by `script`, as in stack traces. */
+    functions: Vec<String>,
+    /* Only trace instructions compiled from these source lines. */
+    lines: Option<RangeInclusive<usize>>,
+}
+
+impl Tracer {
+    pub fn new(out: impl Write + 'static) -> Self {
+        Tracer {
+            out: Box::new(out),
+            functions: Vec::new(),
+            lines: None,
+        }
+    }
+
+    pub fn functions(mut self, functions: Vec<String>) -> Self {
+        self.functions = functions;
+        self
+    }
+
+    pub fn lines(mut self, lines: RangeInclusive<usize>) -> Self {
+        self.lines = Some(lines);
+        self
+    }
+
+    /* Trace the instruction at `offset` in `function`, if it passes the
+     * filters. */
+    pub(crate) fn trace(
+        &mut self,
+        stack: &VecDeque<Value>,
+        function: &ObjFunction,
+        offset: usize,
+    ) -> io::Result<()> {
+        if !self.functions.is_empty() {
+            let name = function
+                .name
+                .as_ref()
+                .and_then(|name| name.as_string().map(|name| name.chars.as_str()))
+                .unwrap_or("script");
+            if !self.functions.iter().any(|function| function == name) {
+                return Ok(());
+            }
+        }
+        if let Some(lines) = &self.lines {
+            if !lines.contains(&function.chunk.line(offset)) {
+                return Ok(());
+            }
+        }
+
+        for value in stack {
+            write!(self.out, "[ {} ]", value)?;
+        }
+        Disassembler::new(&mut self.out).instruction(&function.chunk, offset)?;
+        Ok(())
+    }
+
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
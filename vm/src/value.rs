@@ -1,14 +1,52 @@
 use std::fmt::Display;
 
-#[derive(Clone, Copy)]
+use crate::object::{ObjClass, ObjInstance, ObjRef};
+
+/* Strings are interned, so comparing objects by identity also compares
+ * strings by content. */
+#[derive(Clone, Copy, PartialEq)]
 pub enum Value {
+    Bool(bool),
+    Nil,
     Double(f64),
+    Obj(ObjRef),
+}
+
+impl Value {
+    /* Nil and false are falsey and every other value is truthy. */
+    pub fn is_falsey(&self) -> bool {
+        matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn as_class(&self) -> Option<&ObjClass> {
+        match self {
+            Value::Obj(obj) => obj.as_class(),
+            _ => None,
+        }
+    }
+
+    pub fn as_instance(&self) -> Option<&ObjInstance> {
+        match self {
+            Value::Obj(obj) => obj.as_instance(),
+            _ => None,
+        }
+    }
+
+    pub fn as_string(&self) -> Option<&str> {
+        match self {
+            Value::Obj(obj) => obj.as_string().map(|s| s.chars.as_str()),
+            _ => None,
+        }
+    }
 }
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Nil => write!(f, "nil"),
             Value::Double(n) => write!(f, "{}", n),
+            Value::Obj(obj) => write!(f, "{}", obj),
         }
     }
 }
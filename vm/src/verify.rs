@@ -0,0 +1,299 @@
+/* Checks a function's bytecode before it runs, so the vm can trust its
+ * shape: every opcode is known and has all its operands, constant, local
+ * and upvalue operands are in range, jumps land on instruction boundaries,
+ * and no path through the code pops more than it has pushed. Functions
+ * loaded from .loxc files haven't been through the compiler, so this is
+ * what keeps a corrupt file from making the vm read out of bounds.
+ *
+ * What values end up on the stack isn't checked. The vm still checks the
+ * types of the values each instruction uses, like it has to for ones that
+ * depend on the program's input. */
+
+use anyhow::bail;
+
+use crate::{
+    chunk::{Instruction, OpCode, Operand},
+    object::{ObjFunction, ObjRef},
+    value::Value,
+};
+
+/* Verify a script and every function nested in its constants. The vm
+ * calls a script with no arguments and no enclosing closure, so it can't
+ * expect either. */
+pub fn verify(script: ObjRef) -> anyhow::Result<()> {
+    let Some(function) = script.as_function() else {
+        bail!("Invalid bytecode: expected a function.");
+    };
+    if function.arity != 0 {
+        bail!("Invalid bytecode: script function can't take parameters.");
+    }
+    if function.upvalue_count != 0 {
+        bail!("Invalid bytecode: script function can't have upvalues.");
+    }
+    verify_function(function)
+}
+
+fn verify_function(function: &ObjFunction) -> anyhow::Result<()> {
+    Verifier::new(function).verify()?;
+
+    for constant in &function.chunk.constants {
+        if let Value::Obj(obj) = constant {
+            if let Some(nested) = obj.as_function() {
+                verify_function(nested)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    function: &'a ObjFunction,
+    /* The instruction starting at each offset, if one does. */
+    instructions: Vec<Option<Instruction>>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(function: &'a ObjFunction) -> Self {
+        Verifier {
+            function,
+            instructions: Vec::new(),
+        }
+    }
+
+    fn verify(&mut self) -> anyhow::Result<()> {
+        self.decode()?;
+        self.check_stack()
+    }
+
+    /* Split the code into instructions and check each one's operands on
+     * their own. */
+    fn decode(&mut self) -> anyhow::Result<()> {
+        let chunk = &self.function.chunk;
+        self.instructions = vec![None; chunk.code.len()];
+
+        for instruction in chunk.instructions() {
+            let instruction =
+                instruction.map_err(|err| self.error(err.offset, &err.kind.to_string()))?;
+            self.check_operands(&instruction)?;
+            let offset = instruction.offset;
+            self.instructions[offset] = Some(instruction);
+        }
+        Ok(())
+    }
+
+    fn check_operands(&self, instruction: &Instruction) -> anyhow::Result<()> {
+        let offset = instruction.offset;
+        match (instruction.opcode, &instruction.operand) {
+            (OpCode::Constant | OpCode::ConstantLong, Operand::Constant(index)) => {
+                self.constant(offset, *index).map(|_| ())
+            }
+            (
+                _,
+                Operand::Constant(index)
+                | Operand::Invoke {
+                    constant: index, ..
+                },
+            ) => self.name(offset, *index),
+            (OpCode::GetUpvalue | OpCode::SetUpvalue, Operand::Byte(index)) => {
+                self.upvalue(offset, *index)
+            }
+            // Captured locals are checked against the stack height later.
+            (_, Operand::Closure { upvalues, .. }) => upvalues
+                .iter()
+                .filter(|capture| !capture.is_local)
+                .try_for_each(|capture| self.upvalue(offset, capture.index)),
+            _ => Ok(()),
+        }
+    }
+
+    /* Follow every path through the code, tracking how many values are on
+     * the stack in this function's frame. Paths that meet must agree. */
+    fn check_stack(&self) -> anyhow::Result<()> {
+        let code = &self.function.chunk.code;
+        if code.is_empty() {
+            return Err(self.error(0, "empty function"));
+        }
+
+        // The callee and its arguments are in the frame when it starts.
+        let mut heights: Vec<Option<usize>> = vec![None; code.len()];
+        let mut worklist = vec![(0, 1 + self.function.arity)];
+
+        while let Some((offset, height)) = worklist.pop() {
+            match heights[offset] {
+                Some(seen) if seen == height => continue,
+                Some(seen) => {
+                    return Err(self.error(
+                        offset,
+                        &format!("stack height {} here, but {} on another path", height, seen),
+                    ))
+                }
+                None => heights[offset] = Some(height),
+            }
+
+            let instruction = self.instructions[offset]
+                .as_ref()
+                .expect("paths only visit instruction starts");
+            let (pops, pushes) = self.stack_effect(instruction, height)?;
+            if pops > height {
+                return Err(self.error(offset, "stack underflow"));
+            }
+            let height = height - pops + pushes;
+
+            let next = offset + instruction.len;
+            match (instruction.opcode, &instruction.operand) {
+                (OpCode::Return, _) => (),
+                (OpCode::JumpIfFalse, Operand::Jump(target)) => {
+                    worklist.push((self.jump_target(offset, *target)?, height));
+                    worklist.push((self.fall_through(offset, next)?, height));
+                }
+                (_, Operand::Jump(target)) => {
+                    worklist.push((self.jump_target(offset, *target)?, height))
+                }
+                _ => worklist.push((self.fall_through(offset, next)?, height)),
+            }
+        }
+        Ok(())
+    }
+
+    /* How many values an instruction pops, then pushes. Instructions that
+     * only peek count as popping and pushing back what they need. */
+    fn stack_effect(
+        &self,
+        instruction: &Instruction,
+        height: usize,
+    ) -> anyhow::Result<(usize, usize)> {
+        let offset = instruction.offset;
+        Ok(match (instruction.opcode, &instruction.operand) {
+            (
+                OpCode::Constant
+                | OpCode::ConstantLong
+                | OpCode::Nil
+                | OpCode::True
+                | OpCode::False
+                | OpCode::GetGlobal
+                | OpCode::GetGlobalLong
+                | OpCode::GetUpvalue
+                | OpCode::Class
+                | OpCode::ClassLong,
+                _,
+            ) => (0, 1),
+            (
+                OpCode::Equal
+                | OpCode::Greater
+                | OpCode::Less
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::SetProperty
+                | OpCode::SetPropertyLong
+                | OpCode::GetSuper
+                | OpCode::GetSuperLong
+                | OpCode::Inherit
+                | OpCode::Method
+                | OpCode::MethodLong,
+                _,
+            ) => (2, 1),
+            (
+                OpCode::Not
+                | OpCode::Negate
+                | OpCode::SetGlobal
+                | OpCode::SetGlobalLong
+                | OpCode::SetUpvalue
+                | OpCode::GetProperty
+                | OpCode::GetPropertyLong
+                | OpCode::JumpIfFalse,
+                _,
+            ) => (1, 1),
+            (
+                OpCode::Print
+                | OpCode::Pop
+                | OpCode::DefineGlobal
+                | OpCode::DefineGlobalLong
+                | OpCode::CloseUpvalue
+                | OpCode::Return,
+                _,
+            ) => (1, 0),
+            (OpCode::Jump | OpCode::Loop, _) => (0, 0),
+            (OpCode::GetLocal, Operand::Byte(slot)) => {
+                self.local(offset, *slot, height)?;
+                (0, 1)
+            }
+            (OpCode::SetLocal, Operand::Byte(slot)) => {
+                self.local(offset, *slot, height)?;
+                (1, 1)
+            }
+            (OpCode::Call, Operand::Byte(arg_count)) => (*arg_count as usize + 1, 1),
+            (OpCode::Invoke | OpCode::InvokeLong, Operand::Invoke { arg_count, .. }) => {
+                (*arg_count as usize + 1, 1)
+            }
+            // The superclass is on top of the arguments.
+            (OpCode::SuperInvoke | OpCode::SuperInvokeLong, Operand::Invoke { arg_count, .. }) => {
+                (*arg_count as usize + 2, 1)
+            }
+            (OpCode::Closure | OpCode::ClosureLong, Operand::Closure { upvalues, .. }) => {
+                for capture in upvalues.iter().filter(|capture| capture.is_local) {
+                    self.local(offset, capture.index, height)?;
+                }
+                (0, 1)
+            }
+            (opcode, operand) => unreachable!("{:?} decoded with {:?}", opcode, operand),
+        })
+    }
+
+    fn jump_target(&self, offset: usize, target: usize) -> anyhow::Result<usize> {
+        match self.instructions.get(target) {
+            Some(Some(_)) => Ok(target),
+            _ => Err(self.error(offset, "jump into the middle of an instruction")),
+        }
+    }
+
+    fn fall_through(&self, offset: usize, next: usize) -> anyhow::Result<usize> {
+        if next >= self.function.chunk.code.len() {
+            return Err(self.error(offset, "execution runs off the end of the code"));
+        }
+        Ok(next)
+    }
+
+    fn local(&self, offset: usize, slot: u8, height: usize) -> anyhow::Result<()> {
+        if slot as usize >= height {
+            return Err(self.error(offset, "local slot out of range"));
+        }
+        Ok(())
+    }
+
+    fn upvalue(&self, offset: usize, index: u8) -> anyhow::Result<()> {
+        if index as usize >= self.function.upvalue_count {
+            return Err(self.error(offset, "upvalue index out of range"));
+        }
+        Ok(())
+    }
+
+    fn constant(&self, offset: usize, index: usize) -> anyhow::Result<Value> {
+        match self.function.chunk.constants.get(index) {
+            Some(constant) => Ok(*constant),
+            None => Err(self.error(offset, "constant index out of range")),
+        }
+    }
+
+    /* Instructions that look something up by name take a string constant. */
+    fn name(&self, offset: usize, index: usize) -> anyhow::Result<()> {
+        if self.constant(offset, index)?.as_string().is_none() {
+            return Err(self.error(offset, "name operand is not a string"));
+        }
+        Ok(())
+    }
+
+    fn error(&self, offset: usize, message: &str) -> anyhow::Error {
+        let function = match self.function.name {
+            Some(name) => format!("{}()", name),
+            None => "script".to_owned(),
+        };
+        anyhow::anyhow!(
+            "Invalid bytecode in {} at offset {}: {}.",
+            function,
+            offset,
+            message
+        )
+    }
+}
@@ -1,15 +1,41 @@
-use std::collections::VecDeque;
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    io::{Read, Write},
+    marker::PhantomData,
+};
 
 use crate::{
+    assembler::Assembler,
     chunk::{Chunk, OpCode},
-    debug::Disassembler,
+    compiler::Compiler,
+    loxc::{self, Loader},
+    memory::{GcConfig, GcStats, Heap, Trace},
+    object::{
+        ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjKind, ObjRef,
+        ObjUpvalue, UpvalueState,
+    },
+    suggest::did_you_mean,
+    table::Table,
+    trace::Tracer,
     value::Value,
+    verify,
 };
 
-pub struct Vm<'a> {
-    chunk: Option<&'a Chunk>,
-    index: usize,
+/* Calls nested deeper than this are a stack overflow. */
+const FRAMES_MAX: usize = 64;
+
+pub struct Vm {
+    frames: Vec<CallFrame>,
     stack: VecDeque<Value>,
+    globals: Table,
+    /* Upvalues still pointing into the stack, so closures capturing the same
+     * variable share one upvalue. */
+    open_upvalues: Vec<ObjRef>,
+    /* The interned name initializers are looked up by. */
+    init_string: ObjRef,
+    heap: Heap,
+    tracer: Option<Tracer>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -18,61 +44,742 @@ pub enum InterpretError {
     Runtime,
 }
 
-impl<'a> Vm<'a> {
+impl std::fmt::Display for InterpretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InterpretError::Compile => write!(f, "Compile error"),
+            InterpretError::Runtime => write!(f, "Runtime error"),
+        }
+    }
+}
+
+impl std::error::Error for InterpretError {}
+
+/* A script function from `Vm::compile`, `Vm::load_compiled` or
+ * `Vm::assemble`. Nothing roots it, so it keeps the vm borrowed, and so
+ * unable to allocate and collect it, for as long as it's in use. */
+#[derive(Debug)]
+pub struct Script<'vm> {
+    function: ObjRef,
+    vm: PhantomData<&'vm mut Vm>,
+}
+
+impl Script<'_> {
+    fn new(function: ObjRef) -> Self {
+        Script {
+            function,
+            vm: PhantomData,
+        }
+    }
+
+    pub fn function(&self) -> ObjRef {
+        self.function
+    }
+}
+
+/* An ongoing function call. */
+struct CallFrame {
+    closure: ObjRef,
+    ip: usize,
+    /* Where the function's slot window starts on the value stack. Slot zero
+     * holds the function itself, followed by its arguments and locals. */
+    slots: usize,
+}
+
+impl CallFrame {
+    fn closure(&self) -> &ObjClosure {
+        self.closure.as_closure().expect("frame runs a closure")
+    }
+
+    fn function(&self) -> &ObjFunction {
+        self.closure()
+            .function
+            .as_function()
+            .expect("closure wraps a function")
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.function().chunk
+    }
+}
+
+impl Trace for CallFrame {
+    fn trace(&self, heap: &mut Heap) {
+        heap.mark_object(self.closure);
+    }
+}
+
+/* Everything the vm keeps alive across a collection. */
+struct Roots<'a> {
+    stack: &'a VecDeque<Value>,
+    frames: &'a Vec<CallFrame>,
+    globals: &'a Table,
+    open_upvalues: &'a Vec<ObjRef>,
+    init_string: ObjRef,
+}
+
+impl Trace for Roots<'_> {
+    fn trace(&self, heap: &mut Heap) {
+        self.stack.trace(heap);
+        self.frames.trace(heap);
+        self.globals.trace(heap);
+        self.open_upvalues.trace(heap);
+        heap.mark_object(self.init_string);
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
     pub fn new() -> Self {
+        Self::with_gc_config(GcConfig::default())
+    }
+
+    pub fn with_gc_config(config: GcConfig) -> Self {
+        let mut heap = Heap::with_config(config);
+        let init_string = heap.alloc_string("init".to_owned(), &[]);
         Vm {
-            chunk: None,
-            index: 0,
+            frames: Vec::new(),
             stack: VecDeque::new(),
+            globals: Table::new(),
+            open_upvalues: Vec::new(),
+            init_string,
+            heap,
+            tracer: None,
         }
     }
-    pub fn interpret(&'a mut self, chunk: &'a Chunk) -> anyhow::Result<()> {
-        self.chunk = Some(chunk);
-        self.index = 0;
-        self.run()
+
+    /* Trace every instruction run from now on, or stop tracing. */
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    pub fn interpret(&mut self, source: &[u8]) -> anyhow::Result<()> {
+        let function = self.compile_function(source)?;
+        self.run_script(function)
+    }
+
+    /* Compile a script and save it in the .loxc format instead of running it. */
+    pub fn compile_to(&mut self, source: &[u8], out: &mut impl Write) -> anyhow::Result<()> {
+        let function = self.compile_function(source)?;
+        loxc::write(function, out)
+    }
+
+    /* Run a script saved by `compile_to`, without compiling it again. */
+    pub fn interpret_compiled(&mut self, input: impl Read) -> anyhow::Result<()> {
+        let function = self.load_function(input)?;
+        self.run_script(function)
+    }
+
+    /* Run a script written in the assembler's syntax. */
+    pub fn interpret_assembly(&mut self, source: &str) -> anyhow::Result<()> {
+        let function = self.assemble_function(source)?;
+        self.run_script(function)
+    }
+
+    /* The script function for some source, without running it. */
+    pub fn compile(&mut self, source: &[u8]) -> anyhow::Result<Script<'_>> {
+        self.compile_function(source).map(Script::new)
+    }
+
+    /* Like `compile`, for a script saved in the .loxc format. */
+    pub fn load_compiled(&mut self, input: impl Read) -> anyhow::Result<Script<'_>> {
+        self.load_function(input).map(Script::new)
+    }
+
+    /* Like `compile`, for a script written in the assembler's syntax. */
+    pub fn assemble(&mut self, source: &str) -> anyhow::Result<Script<'_>> {
+        self.assemble_function(source).map(Script::new)
+    }
+
+    /* Nothing roots the functions these return, so they are only good
+     * until the vm next allocates. */
+    fn compile_function(&mut self, source: &[u8]) -> anyhow::Result<ObjRef> {
+        self.with_roots(|heap, roots| Compiler::new(source, heap, roots).compile())
+    }
+
+    fn load_function(&mut self, input: impl Read) -> anyhow::Result<ObjRef> {
+        self.with_roots(|heap, roots| Loader::new(input, heap, roots).load())
+    }
+
+    fn assemble_function(&mut self, source: &str) -> anyhow::Result<ObjRef> {
+        self.with_roots(|heap, roots| Assembler::new(source, heap, roots).assemble())
+    }
+
+    /* Every script is verified before it runs, whether it was just compiled
+     * or loaded from a file that could have been tampered with. */
+    fn run_script(&mut self, function: ObjRef) -> anyhow::Result<()> {
+        verify::verify(function)?;
+        self.stack.push_back(Value::Obj(function));
+        let closure = self.alloc(ObjKind::Closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+        }));
+        self.stack.pop_back();
+        self.stack.push_back(Value::Obj(closure));
+        self.call(closure, 0)?;
+        let result = self.run();
+        // The process may exit straight after an error, so the trace is
+        // written out now.
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        result
     }
 
     pub fn run(&mut self) -> anyhow::Result<()> {
         loop {
-            for value in &self.stack {
-                print!("[ {} ]", value);
+            if let Some(tracer) = &mut self.tracer {
+                let frame = self.frames.last().expect("frame");
+                tracer.trace(&self.stack, frame.function(), frame.ip)?;
             }
-            Disassembler::disassemble_instruction(self.chunk.expect("chunk"), self.index);
             let instruction = self.read_byte();
-            match instruction.try_into().expect("valid opcode") {
-                OpCode::Constant => {
-                    let constant = *self.read_constant();
+            // Long forms only differ in how wide their constant index is.
+            let opcode: OpCode = instruction.try_into().expect("valid opcode");
+            match opcode {
+                OpCode::Constant | OpCode::ConstantLong => {
+                    let constant = self.read_constant(opcode);
                     self.stack.push_back(constant);
                 }
+                OpCode::Nil => self.stack.push_back(Value::Nil),
+                OpCode::True => self.stack.push_back(Value::Bool(true)),
+                OpCode::False => self.stack.push_back(Value::Bool(false)),
+                OpCode::Equal => {
+                    let b = self.stack.pop_back().expect("value");
+                    let a = self.stack.pop_back().expect("value");
+                    self.stack.push_back(Value::Bool(a == b));
+                }
+                OpCode::Greater => self.binary_op(|a, b| Value::Bool(a > b))?,
+                OpCode::Less => self.binary_op(|a, b| Value::Bool(a < b))?,
+                OpCode::Add => {
+                    if self.peek(0).as_string().is_some() && self.peek(1).as_string().is_some() {
+                        self.concatenate();
+                    } else if let (Value::Double(_), Value::Double(_)) =
+                        (self.peek(1), self.peek(0))
+                    {
+                        self.binary_op(|a, b| Value::Double(a + b))?;
+                    } else {
+                        return Err(
+                            self.runtime_error("Operands must be two numbers or two strings.")
+                        );
+                    }
+                }
+                OpCode::Subtract => self.binary_op(|a, b| Value::Double(a - b))?,
+                OpCode::Multiply => self.binary_op(|a, b| Value::Double(a * b))?,
+                OpCode::Divide => self.binary_op(|a, b| Value::Double(a / b))?,
+                OpCode::Not => {
+                    let value = self.stack.pop_back().expect("value");
+                    self.stack.push_back(Value::Bool(value.is_falsey()));
+                }
                 OpCode::Negate => {
-                    let Value::Double(n) = self.stack.pop_back().expect("value");
+                    let Value::Double(n) = self.peek(0) else {
+                        return Err(self.runtime_error("Operand must be a number."));
+                    };
+                    self.stack.pop_back();
                     self.stack.push_back(Value::Double(-n));
                 }
-                OpCode::Add => self.binary_op(|a, b| a + b),
-                OpCode::Subtract => self.binary_op(|a, b| a - b),
-                OpCode::Multiply => self.binary_op(|a, b| a * b),
-                OpCode::Divide => self.binary_op(|a, b| a / b),
+                OpCode::Print => println!("{}", self.stack.pop_back().expect("value")),
+                OpCode::Pop => {
+                    self.stack.pop_back();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack.push_back(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + self.read_byte() as usize;
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::DefineGlobal | OpCode::DefineGlobalLong => {
+                    let name = self.read_string(opcode);
+                    self.globals.set(name, self.peek(0));
+                    // Only pop once the value is in the table, so a collection
+                    // triggered in between can't free it.
+                    self.stack.pop_back();
+                }
+                OpCode::GetGlobal | OpCode::GetGlobalLong => {
+                    let name = self.read_string(opcode);
+                    let Some(value) = self.globals.get(name) else {
+                        return Err(self.undefined_variable(name));
+                    };
+                    self.stack.push_back(value);
+                }
+                OpCode::SetGlobal | OpCode::SetGlobalLong => {
+                    let name = self.read_string(opcode);
+                    // Assigning never creates a global, so undo the insert
+                    // if the variable didn't exist.
+                    if self.globals.set(name, self.peek(0)) {
+                        self.globals.delete(name);
+                        return Err(self.undefined_variable(name));
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    let value = match upvalue.as_upvalue().expect("upvalue").state.get() {
+                        UpvalueState::Open(slot) => self.stack[slot],
+                        UpvalueState::Closed(value) => value,
+                    };
+                    self.stack.push_back(value);
+                }
+                OpCode::SetUpvalue => {
+                    let slot = self.read_byte() as usize;
+                    let upvalue = self.frame().closure().upvalues[slot];
+                    let upvalue = upvalue.as_upvalue().expect("upvalue");
+                    match upvalue.state.get() {
+                        UpvalueState::Open(slot) => self.stack[slot] = self.peek(0),
+                        UpvalueState::Closed(_) => {
+                            upvalue.state.set(UpvalueState::Closed(self.peek(0)))
+                        }
+                    }
+                }
+                OpCode::GetProperty | OpCode::GetPropertyLong => {
+                    let receiver = self.peek(0);
+                    let Some(instance) = receiver.as_instance() else {
+                        return Err(self.runtime_error("Only instances have properties."));
+                    };
+                    let name = self.read_string(opcode);
+
+                    // Fields shadow methods.
+                    let field = instance.fields.borrow().get(name);
+                    if let Some(value) = field {
+                        self.stack.pop_back();
+                        self.stack.push_back(value);
+                    } else {
+                        let class = instance.class;
+                        self.bind_method(class, name)?;
+                    }
+                }
+                OpCode::SetProperty | OpCode::SetPropertyLong => {
+                    let receiver = self.peek(1);
+                    let Some(instance) = receiver.as_instance() else {
+                        return Err(self.runtime_error("Only instances have fields."));
+                    };
+                    let name = self.read_string(opcode);
+                    instance.fields.borrow_mut().set(name, self.peek(0));
+
+                    // Leave the assigned value in place of the instance.
+                    let value = self.stack.pop_back().expect("value");
+                    self.stack.pop_back();
+                    self.stack.push_back(value);
+                }
+                OpCode::GetSuper | OpCode::GetSuperLong => {
+                    let name = self.read_string(opcode);
+                    let superclass = self.pop_superclass()?;
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip += offset as usize;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short();
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += offset as usize;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short();
+                    self.frame_mut().ip -= offset as usize;
+                }
+                OpCode::Call => {
+                    let arg_count = self.read_byte() as usize;
+                    self.call_value(self.peek(arg_count), arg_count)?;
+                }
+                OpCode::Invoke | OpCode::InvokeLong => {
+                    let method = self.read_string(opcode);
+                    let arg_count = self.read_byte() as usize;
+                    self.invoke(method, arg_count)?;
+                }
+                OpCode::SuperInvoke | OpCode::SuperInvokeLong => {
+                    let method = self.read_string(opcode);
+                    let arg_count = self.read_byte() as usize;
+                    let superclass = self.pop_superclass()?;
+                    self.invoke_from_class(superclass, method, arg_count)?;
+                }
+                OpCode::Closure | OpCode::ClosureLong => {
+                    let Value::Obj(function) = self.read_constant(opcode) else {
+                        unreachable!("operand is a function constant");
+                    };
+                    let upvalue_count = function.as_function().expect("function").upvalue_count;
+                    let mut upvalues = Vec::with_capacity(upvalue_count);
+                    for _ in 0..upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        upvalues.push(if is_local {
+                            self.capture_upvalue(self.frame().slots + index)
+                        } else {
+                            self.frame().closure().upvalues[index]
+                        });
+                    }
+                    // Every upvalue is reachable from an open upvalue or the
+                    // current closure, so they survive this allocation.
+                    let closure = self.alloc(ObjKind::Closure(ObjClosure { function, upvalues }));
+                    self.stack.push_back(Value::Obj(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.stack.pop_back();
+                }
+                OpCode::Class | OpCode::ClassLong => {
+                    let name = self.read_string(opcode);
+                    let class = self.alloc(ObjKind::Class(ObjClass {
+                        name,
+                        methods: RefCell::new(Table::new()),
+                    }));
+                    self.stack.push_back(Value::Obj(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = self.peek(1);
+                    let Some(superclass) = superclass.as_class() else {
+                        return Err(self.runtime_error("Superclass must be a class."));
+                    };
+                    let subclass = self.peek(0);
+                    let Some(subclass) = subclass.as_class() else {
+                        return Err(self.runtime_error("Only classes can inherit."));
+                    };
+                    // The compiler rejects this, but bytecode from a file could
+                    // still ask for it, and the methods table can't be copied
+                    // into itself.
+                    if self.peek(0) == self.peek(1) {
+                        return Err(self.runtime_error("A class can't inherit from itself."));
+                    }
+                    // Copy the inherited methods down now, so method lookup
+                    // never has to walk the class hierarchy. Methods the
+                    // subclass defines are added afterwards and override them.
+                    superclass
+                        .methods
+                        .borrow()
+                        .add_all(&mut subclass.methods.borrow_mut());
+                    self.stack.pop_back();
+                }
+                OpCode::Method | OpCode::MethodLong => {
+                    let name = self.read_string(opcode);
+                    let method = self.peek(0);
+                    if !matches!(method, Value::Obj(obj) if obj.as_closure().is_some()) {
+                        return Err(self.runtime_error("Methods must be functions."));
+                    }
+                    let class = self.peek(1);
+                    let Some(class) = class.as_class() else {
+                        return Err(self.runtime_error("Only classes have methods."));
+                    };
+                    class.methods.borrow_mut().set(name, method);
+                    self.stack.pop_back();
+                }
                 OpCode::Return => {
-                    println!("{}", self.stack.pop_back().expect("non-empty stack"));
-                    break Ok(());
+                    let result = self.stack.pop_back().expect("value");
+                    let frame = self.frames.pop().expect("frame");
+                    self.close_upvalues(frame.slots);
+                    if self.frames.is_empty() {
+                        // Pop the script function itself.
+                        self.stack.pop_back();
+                        break Ok(());
+                    }
+
+                    self.stack.truncate(frame.slots);
+                    self.stack.push_back(result);
                 }
             }
         }
     }
 
-    fn binary_op(&mut self, op: impl Fn(f64, f64) -> f64) {
-        let Value::Double(b) = self.stack.pop_back().expect("value");
-        let Value::Double(a) = self.stack.pop_back().expect("value");
-        self.stack.push_back(Value::Double(op(a, b)));
+    fn call_value(&mut self, callee: Value, arg_count: usize) -> anyhow::Result<()> {
+        let Value::Obj(obj) = callee else {
+            return Err(self.runtime_error("Can only call functions and classes."));
+        };
+        let callee_slot = self.stack.len() - arg_count - 1;
+        match &obj.kind {
+            ObjKind::Closure(_) => self.call(obj, arg_count),
+            ObjKind::BoundMethod(bound) => {
+                self.stack[callee_slot] = bound.receiver;
+                self.call(bound.method, arg_count)
+            }
+            ObjKind::Class(class) => {
+                // The class is still on the stack, so it survives allocating
+                // its instance.
+                let instance = self.alloc(ObjKind::Instance(ObjInstance {
+                    class: obj,
+                    fields: RefCell::new(Table::new()),
+                }));
+                self.stack[callee_slot] = Value::Obj(instance);
+
+                let initializer = class.methods.borrow().get(self.init_string);
+                match initializer {
+                    Some(Value::Obj(initializer)) => self.call(initializer, arg_count),
+                    _ if arg_count != 0 => {
+                        let message = format!("Expected 0 arguments but got {}.", arg_count);
+                        Err(self.runtime_error(&message))
+                    }
+                    _ => Ok(()),
+                }
+            }
+            _ => Err(self.runtime_error("Can only call functions and classes.")),
+        }
     }
+
+    /* Call the method `name` on the receiver below the arguments, without
+     * creating a bound method. */
+    fn invoke(&mut self, name: ObjRef, arg_count: usize) -> anyhow::Result<()> {
+        let receiver = self.peek(arg_count);
+        let Some(instance) = receiver.as_instance() else {
+            return Err(self.runtime_error("Only instances have methods."));
+        };
+
+        // A field holding a function is called like any other value.
+        let field = instance.fields.borrow().get(name);
+        if let Some(value) = field {
+            let callee_slot = self.stack.len() - arg_count - 1;
+            self.stack[callee_slot] = value;
+            return self.call_value(value, arg_count);
+        }
+
+        self.invoke_from_class(instance.class, name, arg_count)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        arg_count: usize,
+    ) -> anyhow::Result<()> {
+        let method = class.as_class().expect("class").methods.borrow().get(name);
+        let Some(Value::Obj(method)) = method else {
+            return Err(self.undefined_property(self.peek(arg_count), class, name));
+        };
+        self.call(method, arg_count)
+    }
+
+    /* The compiler only ever leaves a class under `super`, but bytecode
+     * from a file could leave anything there. */
+    fn pop_superclass(&mut self) -> anyhow::Result<ObjRef> {
+        match self.stack.pop_back().expect("value") {
+            Value::Obj(obj) if obj.as_class().is_some() => Ok(obj),
+            _ => Err(self.runtime_error("Superclass must be a class.")),
+        }
+    }
+
+    /* Replace the instance on top of the stack with its class's method
+     * `name` bound to it. */
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> anyhow::Result<()> {
+        let method = class.as_class().expect("class").methods.borrow().get(name);
+        let Some(Value::Obj(method)) = method else {
+            return Err(self.undefined_property(self.peek(0), class, name));
+        };
+
+        let bound = self.alloc(ObjKind::BoundMethod(ObjBoundMethod {
+            receiver: self.peek(0),
+            method,
+        }));
+        self.stack.pop_back();
+        self.stack.push_back(Value::Obj(bound));
+        Ok(())
+    }
+
+    /* Push a frame whose slot window starts at the callee, which sits just
+     * below its arguments on the stack. */
+    fn call(&mut self, closure: ObjRef, arg_count: usize) -> anyhow::Result<()> {
+        let function = closure.as_closure().expect("closure").function;
+        let arity = function.as_function().expect("function").arity;
+        if arg_count != arity {
+            let message = format!("Expected {} arguments but got {}.", arity, arg_count);
+            return Err(self.runtime_error(&message));
+        }
+        if self.frames.len() == FRAMES_MAX {
+            return Err(self.runtime_error("Stack overflow."));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.stack.len() - arg_count - 1,
+        });
+        Ok(())
+    }
+
+    /* The upvalue for a stack slot, reusing an open one if the variable was
+     * already captured. */
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let existing = self.open_upvalues.iter().find(|upvalue| {
+            matches!(
+                upvalue.as_upvalue().expect("upvalue").state.get(),
+                UpvalueState::Open(open) if open == slot
+            )
+        });
+        if let Some(&upvalue) = existing {
+            return upvalue;
+        }
+
+        let upvalue = self.alloc(ObjKind::Upvalue(ObjUpvalue {
+            state: Cell::new(UpvalueState::Open(slot)),
+        }));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    /* Move every variable at or above `last` off the stack into its upvalue. */
+    fn close_upvalues(&mut self, last: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let upvalue = upvalue.as_upvalue().expect("upvalue");
+            match upvalue.state.get() {
+                UpvalueState::Open(slot) if slot >= last => {
+                    upvalue.state.set(UpvalueState::Closed(stack[slot]));
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("frame")
+    }
+
+    fn binary_op(&mut self, op: impl Fn(f64, f64) -> Value) -> anyhow::Result<()> {
+        let (Value::Double(a), Value::Double(b)) = (self.peek(1), self.peek(0)) else {
+            return Err(self.runtime_error("Operands must be numbers."));
+        };
+        self.stack.pop_back();
+        self.stack.pop_back();
+        self.stack.push_back(op(a, b));
+        Ok(())
+    }
+
+    fn concatenate(&mut self) {
+        let b = self.stack.pop_back().expect("value");
+        let a = self.stack.pop_back().expect("value");
+        let chars = [
+            a.as_string().expect("string"),
+            b.as_string().expect("string"),
+        ]
+        .concat();
+        let result = self.alloc_string(chars);
+        self.stack.push_back(Value::Obj(result));
+    }
+
+    /* Run `f` with the heap and everything the vm keeps alive, for
+     * allocating in a way that can collect garbage. */
+    fn with_roots<T>(&mut self, f: impl FnOnce(&mut Heap, &Roots) -> T) -> T {
+        let roots = Roots {
+            stack: &self.stack,
+            frames: &self.frames,
+            globals: &self.globals,
+            open_upvalues: &self.open_upvalues,
+            init_string: self.init_string,
+        };
+        f(&mut self.heap, &roots)
+    }
+
+    fn alloc(&mut self, kind: ObjKind) -> ObjRef {
+        self.with_roots(|heap, roots| heap.alloc(kind, &[roots]))
+    }
+
+    fn alloc_string(&mut self, chars: String) -> ObjRef {
+        self.with_roots(|heap, roots| heap.alloc_string(chars, &[roots]))
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn undefined_variable(&mut self, name: ObjRef) -> anyhow::Error {
+        let candidates = self.globals.iter().map(|(key, _)| key).collect();
+        self.undefined("variable", name, candidates)
+    }
+
+    /* `name` wasn't found on `receiver` or in `class`. Under `super` the
+     * receiver comes from the bytecode, which might not have left an
+     * instance there. */
+    fn undefined_property(
+        &mut self,
+        receiver: Value,
+        class: ObjRef,
+        name: ObjRef,
+    ) -> anyhow::Error {
+        let mut candidates = Vec::new();
+        if let Some(instance) = receiver.as_instance() {
+            candidates.extend(instance.fields.borrow().iter().map(|(key, _)| key));
+        }
+        let class = class.as_class().expect("class");
+        candidates.extend(class.methods.borrow().iter().map(|(key, _)| key));
+        self.undefined("property", name, candidates)
+    }
+
+    /* Report an undefined name, suggesting the closest of `candidates`. */
+    fn undefined(&mut self, what: &str, name: ObjRef, candidates: Vec<ObjRef>) -> anyhow::Error {
+        let name = &name.as_string().expect("string").chars;
+        let names = candidates
+            .iter()
+            .filter_map(|key| key.as_string())
+            .map(|key| key.chars.as_str());
+        let message = match did_you_mean(name, names) {
+            Some(suggestion) => format!(
+                "Undefined {} '{}'. Did you mean '{}'?",
+                what, name, suggestion
+            ),
+            None => format!("Undefined {} '{}'.", what, name),
+        };
+        self.runtime_error(&message)
+    }
+
+    /* Report a runtime error followed by a stack trace, innermost call first,
+     * and reset the stack. */
+    fn runtime_error(&mut self, message: &str) -> anyhow::Error {
+        eprintln!("{}", message);
+        for frame in self.frames.iter().rev() {
+            let line = frame.chunk().line(frame.ip - 1);
+            match frame.function().name {
+                Some(name) => eprintln!("[line {}] in {}()", line, name),
+                None => eprintln!("[line {}] in script", line),
+            }
+        }
+        // Closures that escaped keep their captured values, even after the
+        // stack they pointed into is gone.
+        self.close_upvalues(0);
+        self.stack.clear();
+        self.frames.clear();
+        InterpretError::Runtime.into()
+    }
+
     pub fn read_byte(&mut self) -> u8 {
-        let instruction = self.chunk.expect("chunk").code[self.index];
-        self.index += 1;
+        let frame = self.frame_mut();
+        let instruction = frame.chunk().code[frame.ip];
+        frame.ip += 1;
         instruction
     }
 
-    pub fn read_constant(&mut self) -> &Value {
-        let value = &self.chunk.expect("chunk").constants[self.read_byte() as usize];
-        value
+    /* Read a big-endian 16-bit operand. */
+    pub fn read_short(&mut self) -> u16 {
+        u16::from_be_bytes([self.read_byte(), self.read_byte()])
+    }
+
+    /* Read a constant operand. The long forms of instructions address it
+     * with a big-endian 24-bit index. */
+    pub fn read_constant(&mut self, opcode: OpCode) -> Value {
+        let index = if opcode.is_long() {
+            u32::from_be_bytes([0, self.read_byte(), self.read_byte(), self.read_byte()]) as usize
+        } else {
+            self.read_byte() as usize
+        };
+        self.frame().chunk().constants[index]
+    }
+
+    fn read_string(&mut self, opcode: OpCode) -> ObjRef {
+        match self.read_constant(opcode) {
+            Value::Obj(obj) if obj.as_string().is_some() => obj,
+            _ => unreachable!("operand is a string constant"),
+        }
     }
 }
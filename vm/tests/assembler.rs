@@ -0,0 +1,71 @@
+use vm::{debug::Disassembler, verify::verify, vm::Vm};
+
+const SOURCE: &str = include_str!("data/classes.lox");
+/* What the disassembler prints for SOURCE. Regenerate it with
+ * `vm --disassemble tests/data/classes.lox` when the compiler changes. */
+const LISTING: &str = include_str!("data/classes.lasm");
+
+#[test]
+fn disassembles_to_the_golden_listing() {
+    let mut vm = Vm::new();
+    let compiled = vm.compile(SOURCE.as_bytes()).unwrap();
+    assert_eq!(
+        Disassembler::disassemble_function(compiled.function()),
+        LISTING
+    );
+}
+
+#[test]
+fn listing_assembles_back_to_the_same_listing() {
+    let mut vm = Vm::new();
+    let assembled = vm.assemble(LISTING).unwrap();
+    verify(assembled.function()).unwrap();
+    assert_eq!(
+        Disassembler::disassemble_function(assembled.function()),
+        LISTING
+    );
+}
+
+#[test]
+fn rejects_constant_index_too_large_for_the_operand() {
+    let mut vm = Vm::new();
+    for (line, instruction) in [
+        ("CONSTANT 256 1", "CONSTANT"),
+        ("CONSTANT_LONG 99999999999 1", "CONSTANT_LONG"),
+    ] {
+        let err = vm
+            .assemble(&format!("== <script> ==\n{}\nRETURN\n", line))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with(&format!("is too large for {}.", instruction)),
+            "{}",
+            err
+        );
+    }
+}
+
+#[test]
+fn long_forms_round_trip() {
+    // Enough constants that the names after them need three-byte indices.
+    let mut source: String = (0..300).map(|n| format!("print {};\n", n)).collect();
+    source.push_str("class A { m() { return 1; } }\nvar a = A();\nprint a.m();\n");
+    let mut vm = Vm::new();
+    let compiled = vm.compile(source.as_bytes()).unwrap();
+    let listing = Disassembler::disassemble_function(compiled.function());
+    for mnemonic in [
+        "CLASS_LONG",
+        "CLOSURE_LONG",
+        "DEFINE_GLOBAL_LONG",
+        "INVOKE_LONG",
+    ] {
+        assert!(listing.contains(mnemonic), "no {} in the listing", mnemonic);
+    }
+
+    let assembled = vm.assemble(&listing).unwrap();
+    verify(assembled.function()).unwrap();
+    assert_eq!(
+        Disassembler::disassemble_function(assembled.function()),
+        listing
+    );
+}
@@ -0,0 +1,74 @@
+use vm::{debug::Disassembler, loxc, vm::Vm};
+
+const SOURCE: &str = include_str!("data/classes.lox");
+
+#[test]
+fn saved_script_loads_back_unchanged() {
+    let mut vm = Vm::new();
+    let compiled = vm.compile(SOURCE.as_bytes()).unwrap();
+    let listing = Disassembler::disassemble_function(compiled.function());
+    let mut saved = Vec::new();
+    loxc::write(compiled.function(), &mut saved).unwrap();
+
+    let loaded = vm.load_compiled(saved.as_slice()).unwrap();
+    assert_eq!(
+        Disassembler::disassemble_function(loaded.function()),
+        listing
+    );
+
+    let mut resaved = Vec::new();
+    loxc::write(loaded.function(), &mut resaved).unwrap();
+    assert_eq!(resaved, saved);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut vm = Vm::new();
+    let mut saved = Vec::new();
+    vm.compile_to(b"print 1;", &mut saved).unwrap();
+    saved[loxc::MAGIC.len()] = saved[loxc::MAGIC.len()].wrapping_add(1);
+
+    let err = vm.load_compiled(saved.as_slice()).unwrap_err();
+    assert!(err.to_string().starts_with("Unsupported .loxc version"));
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let mut vm = Vm::new();
+    let mut saved = Vec::new();
+    vm.compile_to(SOURCE.as_bytes(), &mut saved).unwrap();
+
+    for len in [0, 3, saved.len() / 2, saved.len() - 1] {
+        assert!(vm.load_compiled(&saved[..len]).is_err(), "length {}", len);
+    }
+    // Cut off inside the version.
+    let err = vm
+        .load_compiled(&saved[..loxc::MAGIC.len() + 1])
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Unexpected end of .loxc file.");
+}
+
+#[test]
+fn rejects_functions_nested_too_deeply() {
+    // Each function's only constant is the next one, far past any limit
+    // the loader could recurse to without a check.
+    const TAG_FUNCTION: u8 = 5;
+    let depth = 100_000;
+    let mut saved = loxc::MAGIC.to_vec();
+    saved.extend_from_slice(&loxc::VERSION.to_le_bytes());
+    for level in 0..depth {
+        // Arity, upvalue count, code length and line runs.
+        saved.extend_from_slice(&[0; 16]);
+        let constant_count: u32 = if level + 1 < depth { 1 } else { 0 };
+        saved.extend_from_slice(&constant_count.to_le_bytes());
+        if constant_count == 1 {
+            saved.push(TAG_FUNCTION);
+        }
+    }
+    // Every function's name flag, innermost first.
+    saved.extend(std::iter::repeat_n(0, depth));
+
+    let mut vm = Vm::new();
+    let err = vm.load_compiled(saved.as_slice()).unwrap_err();
+    assert_eq!(err.to_string(), "Functions nested too deeply.");
+}
@@ -0,0 +1,156 @@
+use vm::{
+    chunk::{Chunk, OpCode},
+    memory::Heap,
+    object::{ObjFunction, ObjKind, ObjRef},
+    value::Value,
+    verify::verify,
+    vm::{InterpretError, Vm},
+};
+
+/* A script function with exactly this code, built by hand since the
+ * compiler and assembler only write well-formed instructions. */
+fn script(heap: &mut Heap, code: &[u8], constants: &[Value]) -> ObjRef {
+    let mut chunk = Chunk::new();
+    for &byte in code {
+        chunk.write(byte, 1);
+    }
+    chunk.constants.extend_from_slice(constants);
+    let function = ObjKind::Function(ObjFunction {
+        arity: 0,
+        upvalue_count: 0,
+        chunk,
+        name: None,
+    });
+    heap.alloc(function, &[])
+}
+
+fn verify_error(code: &[u8], constants: &[Value]) -> String {
+    let mut heap = Heap::new();
+    let function = script(&mut heap, code, constants);
+    verify(function).unwrap_err().to_string()
+}
+
+#[test]
+fn accepts_valid_code() {
+    let mut heap = Heap::new();
+    let code = [
+        OpCode::Constant.into(),
+        0,
+        OpCode::Print.into(),
+        OpCode::Nil.into(),
+        OpCode::Return.into(),
+    ];
+    let function = script(&mut heap, &code, &[Value::Double(1.0)]);
+    verify(function).unwrap();
+}
+
+#[test]
+fn rejects_unknown_opcode() {
+    assert_eq!(
+        verify_error(&[0xff], &[]),
+        "Invalid bytecode in script at offset 0: unknown opcode 255."
+    );
+}
+
+#[test]
+fn rejects_truncated_operand() {
+    let code = [OpCode::Nil.into(), OpCode::ConstantLong.into(), 0, 0];
+    assert_eq!(
+        verify_error(&code, &[Value::Nil]),
+        "Invalid bytecode in script at offset 1: truncated operand."
+    );
+}
+
+#[test]
+fn rejects_jump_into_an_instruction() {
+    // The loop goes back to the operand of the constant.
+    let code = [
+        OpCode::Constant.into(),
+        0,
+        OpCode::Loop.into(),
+        0,
+        4,
+        OpCode::Return.into(),
+    ];
+    assert_eq!(
+        verify_error(&code, &[Value::Nil]),
+        "Invalid bytecode in script at offset 2: jump into the middle of an instruction."
+    );
+}
+
+#[test]
+fn rejects_stack_underflow() {
+    // The frame starts with only the script itself on the stack.
+    let code = [
+        OpCode::Pop.into(),
+        OpCode::Pop.into(),
+        OpCode::Nil.into(),
+        OpCode::Return.into(),
+    ];
+    assert_eq!(
+        verify_error(&code, &[]),
+        "Invalid bytecode in script at offset 1: stack underflow."
+    );
+}
+
+#[test]
+fn rejects_script_with_upvalues() {
+    let mut vm = Vm::new();
+    let err = vm
+        .interpret_assembly("== <script> ==\n.upvalues 1\nGET_UPVALUE 0\nPRINT\nNIL\nRETURN\n")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid bytecode: script function can't have upvalues."
+    );
+}
+
+#[test]
+fn rejects_script_with_parameters() {
+    let mut vm = Vm::new();
+    let err = vm
+        .interpret_assembly("== <script> ==\n.arity 1\nGET_LOCAL 1\nPRINT\nNIL\nRETURN\n")
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Invalid bytecode: script function can't take parameters."
+    );
+}
+
+#[test]
+fn wrong_values_in_verified_code_are_runtime_errors() {
+    // The verifier checks the shape of the code, not what is under `super`.
+    let mut vm = Vm::new();
+    let err = vm
+        .interpret_assembly("== <script> ==\nNIL\nGET_SUPER 0 \"x\"\nNIL\nRETURN\n")
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(InterpretError::Runtime)));
+}
+
+#[test]
+fn class_inheriting_from_itself_is_a_runtime_error() {
+    let mut vm = Vm::new();
+    let err = vm
+        .interpret_assembly(
+            "== <script> ==\nCLASS 0 \"A\"\nGET_LOCAL 1\nINHERIT\nPOP\nNIL\nRETURN\n",
+        )
+        .unwrap_err();
+    assert!(matches!(err.downcast_ref(), Some(InterpretError::Runtime)));
+}
+
+#[test]
+fn missing_super_method_on_a_non_instance_is_a_runtime_error() {
+    for lookup in ["GET_SUPER 2 \"x\"", "SUPER_INVOKE (0 args) 2 \"x\""] {
+        let mut vm = Vm::new();
+        let source = format!(
+            "== <script> ==\nCONSTANT 0 1\nCLASS 1 \"A\"\n{}\nPOP\nNIL\nRETURN\n",
+            lookup
+        );
+        let err = vm.interpret_assembly(&source).unwrap_err();
+        assert!(
+            matches!(err.downcast_ref(), Some(InterpretError::Runtime)),
+            "{}",
+            lookup
+        );
+    }
+}